@@ -0,0 +1,91 @@
+"""Runs Synapse under a line tracer and serves the code it executed to the fuzzer.
+
+    python synapse_coverage.py -m synapse.app.homeserver --config-path /data/homeserver.yaml
+
+Every arc, a pair of consecutive lines in a function of the `synapse` package, gets an ID.
+`GET /delta` on `COVERAGE_PORT` (9100 by default) returns `{"arcs": [...]}` with the IDs of
+the arcs executed since the previous fetch and forgets them, so a fetch after every request
+yields the coverage of that request.
+"""
+
+import json
+import os
+import runpy
+import sys
+import threading
+import zlib
+from http.server import BaseHTTPRequestHandler, ThreadingHTTPServer
+
+PORT = int(os.environ.get("COVERAGE_PORT", "9100"))
+PACKAGE = os.sep + "synapse" + os.sep
+
+# Tuples of (file, previous line, line). The IDs are only computed when fetched, the tracer
+# has to be cheap. Lines are negative at function entry and exit.
+arcs = set()
+lock = threading.Lock()
+
+
+def arc_id(arc):
+    return zlib.crc32("{}:{}:{}".format(*arc).encode())
+
+
+def trace_call(frame, event, arg):
+    filename = frame.f_code.co_filename
+    index = filename.rfind(PACKAGE)
+    if index == -1:
+        return None
+    # Relative to the package, so the IDs do not depend on where Synapse is installed
+    name = filename[index + 1 :]
+    entry = -frame.f_code.co_firstlineno
+    previous = entry
+
+    def trace_line(frame, event, arg):
+        nonlocal previous
+        if event == "line":
+            arcs.add((name, previous, frame.f_lineno))
+            previous = frame.f_lineno
+        elif event == "return":
+            arcs.add((name, previous, entry))
+        return trace_line
+
+    return trace_line
+
+
+class Handler(BaseHTTPRequestHandler):
+    def do_GET(self):
+        global arcs
+        if self.path != "/delta":
+            self.send_error(404)
+            return
+        with lock:
+            taken, arcs = arcs, set()
+        body = json.dumps({"arcs": sorted({arc_id(arc) for arc in taken})}).encode()
+        self.send_response(200)
+        self.send_header("Content-Type", "application/json")
+        self.send_header("Content-Length", str(len(body)))
+        self.end_headers()
+        self.wfile.write(body)
+
+    def log_message(self, format, *args):
+        pass
+
+
+def main():
+    if len(sys.argv) < 3 or sys.argv[1] != "-m":
+        sys.exit(__doc__)
+    module = sys.argv[2]
+
+    # Started before the tracer is installed, so serving the coverage is not traced
+    server = ThreadingHTTPServer(("", PORT), Handler)
+    threading.Thread(target=server.serve_forever, daemon=True).start()
+
+    # Like `python -m`, which finds modules in the working directory instead of the script's
+    sys.path[0] = os.getcwd()
+    sys.argv = [module] + sys.argv[3:]
+    threading.settrace(trace_call)
+    sys.settrace(trace_call)
+    runpy.run_module(module, run_name="__main__", alter_sys=True)
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,184 @@
+use serde::Deserialize;
+use std::{fs, path::Path};
+
+use crate::{
+    error::FuzzError,
+    oracle::MatrixError,
+    server::{ServerVersion, Version},
+};
+
+/// The schema version of the allowlist file this build understands.
+pub const ALLOWLIST_VERSION: u32 = 1;
+
+const DEFAULT_ALLOWLIST: &str = include_str!("../allowlist.toml");
+
+/// Errors which are not considered a finding, loaded from a data file so the list can be
+/// changed without recompiling the fuzzer.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Allowlist {
+    pub version: u32,
+    #[serde(default, rename = "entry")]
+    pub entries: Vec<AllowlistEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AllowlistEntry {
+    pub target: String,
+    pub errcode: String,
+    /// Status the entry applies to. Any 4xx matches if unset.
+    pub status: Option<u16>,
+    /// Substrings of which the error message has to contain one, for errcodes as unspecific as
+    /// `M_UNKNOWN`. Any message matches if empty.
+    #[serde(default)]
+    pub messages: Vec<String>,
+    /// Homeserver implementation (as reported by `/_matrix/federation/v1/version`) the entry
+    /// is scoped to. Applies to every implementation if unset.
+    pub server: Option<String>,
+    /// First server version the entry applies to (inclusive).
+    pub min_version: Option<String>,
+    /// First server version the entry no longer applies to (exclusive).
+    pub max_version: Option<String>,
+    pub reason: String,
+    pub issue: Option<String>,
+}
+
+impl Allowlist {
+    /// Loads the allowlist from `path`, falling back to the copy shipped with the fuzzer if the
+    /// file does not exist.
+    pub fn load(path: &Path) -> Result<Allowlist, FuzzError> {
+        let error = |reason: String| FuzzError::Config {
+            path: path.to_path_buf(),
+            reason,
+        };
+        let content = if path.exists() {
+            fs::read_to_string(path).map_err(|e| error(e.to_string()))?
+        } else {
+            DEFAULT_ALLOWLIST.to_string()
+        };
+        Allowlist::parse(&content).map_err(error)
+    }
+
+    pub fn parse(content: &str) -> Result<Allowlist, String> {
+        let allowlist: Allowlist = toml::from_str(content).map_err(|e| e.to_string())?;
+        if allowlist.version != ALLOWLIST_VERSION {
+            return Err(format!(
+                "unsupported allowlist version {} (expected {})",
+                allowlist.version, ALLOWLIST_VERSION
+            ));
+        }
+        Ok(allowlist)
+    }
+
+    /// Returns the entry allowing `error` for `target` on `server`, if any.
+    pub fn find(
+        &self,
+        target: &str,
+        server: Option<&ServerVersion>,
+        error: &MatrixError,
+    ) -> Option<&AllowlistEntry> {
+        self.entries
+            .iter()
+            .find(|e| e.target == target && e.matches_server(server) && e.matches_error(error))
+    }
+}
+
+impl AllowlistEntry {
+    pub fn matches_error(&self, error: &MatrixError) -> bool {
+        self.errcode == error.errcode
+            && (self.status.is_none() || self.status == Some(error.status))
+            && self.matches_message(error.error.as_deref().unwrap_or_default())
+    }
+
+    fn matches_message(&self, message: &str) -> bool {
+        self.messages.is_empty() || self.messages.iter().any(|m| message.contains(m.as_str()))
+    }
+
+    /// Entries scoped to a server implementation or version never match an unknown server.
+    pub fn matches_server(&self, server: Option<&ServerVersion>) -> bool {
+        if self.server.is_none() && self.min_version.is_none() && self.max_version.is_none() {
+            return true;
+        }
+        let server = match server {
+            Some(v) => v,
+            None => return false,
+        };
+        if let Some(name) = &self.server {
+            if !server.is(name) {
+                return false;
+            }
+        }
+        let version = server.parsed();
+        if let Some(min) = &self.min_version {
+            if version < Version::parse(min) {
+                return false;
+            }
+        }
+        if let Some(max) = &self.max_version {
+            if version >= Version::parse(max) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(all(test, not(fuzzing)))]
+mod tests {
+    use super::{Allowlist, DEFAULT_ALLOWLIST};
+    use crate::{oracle::MatrixError, server::ServerVersion};
+
+    #[test]
+    fn shipped_allowlist_parses() {
+        Allowlist::parse(DEFAULT_ALLOWLIST).unwrap();
+    }
+
+    #[test]
+    fn scopes_by_server_version() {
+        let allowlist = Allowlist::parse(
+            r#"
+            version = 1
+
+            [[entry]]
+            target = "createRoom"
+            errcode = "M_UNKNOWN"
+            server = "Synapse"
+            min_version = "1.60.0"
+            max_version = "1.66.0"
+            reason = "test"
+            "#,
+        )
+        .unwrap();
+        let entry = &allowlist.entries[0];
+        let server = |name: &str, version: &str| ServerVersion {
+            name: name.to_string(),
+            version: version.to_string(),
+        };
+
+        assert!(entry.matches_server(Some(&server("Synapse", "1.65.0"))));
+        assert!(entry.matches_server(Some(&server("Synapse", "1.66.0rc2"))));
+        assert!(!entry.matches_server(Some(&server("Synapse", "1.66.0"))));
+        assert!(!entry.matches_server(Some(&server("Synapse", "1.59.1"))));
+        assert!(!entry.matches_server(Some(&server("Dendrite", "1.65.0"))));
+        assert!(!entry.matches_server(None));
+    }
+
+    #[test]
+    fn scopes_unspecific_errors_by_message() {
+        let allowlist = Allowlist::parse(DEFAULT_ALLOWLIST).unwrap();
+        let server = ServerVersion {
+            name: "Synapse".to_string(),
+            version: "1.66.0".to_string(),
+        };
+        let error = |message: &str| MatrixError {
+            status: 400,
+            errcode: "M_UNKNOWN".to_string(),
+            error: Some(message.to_string()),
+            extra: Default::default(),
+        };
+
+        let find = |message| allowlist.find("createRoom", Some(&server), &error(message));
+        assert!(find("Invalid characters in room alias").is_some());
+        assert!(find("':' is not permitted in the room alias name.").is_some());
+        assert!(find("Internal error while creating the room").is_none());
+    }
+}
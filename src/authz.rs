@@ -0,0 +1,181 @@
+use reqwest::{Method, StatusCode};
+use serde_json::Value;
+
+use crate::{
+    error::FuzzError,
+    identities::{self, Identity, Role},
+    oracle::Verdict,
+};
+
+/// An invariant check. Errors mean the check could not be set up.
+type Check = fn(&str) -> Result<Option<Verdict>, FuzzError>;
+
+const ROOM_CHECKS: &[Check] = &[outsiders_cannot_read, member_cannot_change_power_levels];
+
+/// Checks the authorization invariants of a room the fuzzer created and returns the first
+/// violation.
+///
+/// The checks compare what the server lets the different [`Role`]s do in the room with what the
+/// room's state allows. Failing to set up a check, e.g. because the fuzzed state makes inviting
+/// impossible, skips it instead of reporting it.
+#[no_coverage]
+pub fn check_room(room_id: &str) -> Option<Verdict> {
+    for check in ROOM_CHECKS {
+        match check(room_id) {
+            Ok(Some(verdict)) => return Some(verdict),
+            Ok(None) => {}
+            Err(e) => println!("Skipping an authorization check for {} ({})", room_id, e),
+        }
+    }
+    None
+}
+
+/// Users outside of a room must not read its state or messages unless anyone may join the room
+/// or its history is world readable.
+#[no_coverage]
+fn outsiders_cannot_read(room_id: &str) -> Result<Option<Verdict>, FuzzError> {
+    let owner = crate::identities().get(Role::Owner)?;
+    let join_rule = state(&owner, room_id, "m.room.join_rules")?;
+    let history = state(&owner, room_id, "m.room.history_visibility")?;
+    if str_field(&join_rule, "join_rule") == Some("public")
+        || str_field(&history, "history_visibility") == Some("world_readable")
+    {
+        return Ok(None);
+    }
+
+    let timeout = crate::config()?.request_timeout();
+    for role in [Role::Stranger, Role::Guest, Role::Deactivated] {
+        let identity = match crate::identities().get(role) {
+            Ok(v) => v,
+            // E.g. guest access is disabled on the server
+            Err(e) => {
+                println!("No {} identity ({})", role, e);
+                continue;
+            }
+        };
+        let reads = [
+            (
+                "state",
+                format!("/_matrix/client/v3/rooms/{}/state", room_id),
+            ),
+            (
+                "messages",
+                format!("/_matrix/client/v3/rooms/{}/messages?dir=b", room_id),
+            ),
+        ];
+        for (what, path) in reads {
+            let (status, content) =
+                identities::send_as(&identity, Method::GET, &path, None, timeout)?;
+            if status.is_success() {
+                return Ok(Some(Verdict::InvariantViolation {
+                    invariant: format!("{} read the {} of a private room", role, what),
+                    status: status.as_u16(),
+                    content,
+                }));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// A member whose power level is below the one required for `m.room.power_levels` must not be
+/// able to raise its own level.
+#[no_coverage]
+fn member_cannot_change_power_levels(room_id: &str) -> Result<Option<Verdict>, FuzzError> {
+    let owner = crate::identities().get(Role::Owner)?;
+    let member = crate::identities().get(Role::Member)?;
+    let timeout = crate::config()?.request_timeout();
+    let invite = serde_json::json!({ "user_id": member.user_id });
+    let path = format!("/_matrix/client/v3/rooms/{}/invite", room_id);
+    if !identities::send_as(&owner, Method::POST, &path, Some(&invite), timeout)?
+        .0
+        .is_success()
+    {
+        return Ok(None);
+    }
+    let path = format!("/_matrix/client/v3/rooms/{}/join", room_id);
+    let empty = serde_json::json!({});
+    if !identities::send_as(&member, Method::POST, &path, Some(&empty), timeout)?
+        .0
+        .is_success()
+    {
+        return Ok(None);
+    }
+
+    let mut levels = state(&owner, room_id, "m.room.power_levels")?;
+    let member_level = power_level(levels.get("users").and_then(|u| u.get(&member.user_id)))
+        .or_else(|| power_level(levels.get("users_default")))
+        .unwrap_or(0);
+    let required = power_level(
+        levels
+            .get("events")
+            .and_then(|e| e.get("m.room.power_levels")),
+    )
+    .or_else(|| power_level(levels.get("state_default")))
+    .unwrap_or(50);
+    if member_level >= required {
+        return Ok(None);
+    }
+
+    let levels_object = match levels.as_object_mut() {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let users = levels_object
+        .entry("users")
+        .or_insert_with(|| Value::Object(Default::default()));
+    if let Some(users) = users.as_object_mut() {
+        users.insert(member.user_id.clone(), Value::from(100));
+    }
+    let path = format!(
+        "/_matrix/client/v3/rooms/{}/state/m.room.power_levels",
+        room_id
+    );
+    let (status, content) =
+        identities::send_as(&member, Method::PUT, &path, Some(&levels), timeout)?;
+    if status.is_success() {
+        return Ok(Some(Verdict::InvariantViolation {
+            invariant: "member without power changed the power levels".to_string(),
+            status: status.as_u16(),
+            content,
+        }));
+    }
+    Ok(None)
+}
+
+/// Content of a state event with an empty state key as seen by `identity`. A missing event is
+/// an empty object.
+#[no_coverage]
+fn state(identity: &Identity, room_id: &str, event_type: &str) -> Result<Value, FuzzError> {
+    let path = format!("/_matrix/client/v3/rooms/{}/state/{}", room_id, event_type);
+    let config = crate::config()?;
+    let timeout = config.request_timeout();
+    let (status, content) = identities::send_as(identity, Method::GET, &path, None, timeout)?;
+    if status == StatusCode::NOT_FOUND {
+        return Ok(Value::Object(Default::default()));
+    }
+    if !status.is_success() {
+        return Err(FuzzError::Status {
+            url: config.url(&path),
+            status: status.as_u16(),
+            body: content,
+        });
+    }
+    serde_json::from_str(&content).map_err(|source| FuzzError::Decode {
+        url: config.url(&path),
+        source,
+    })
+}
+
+fn str_field<'a>(content: &'a Value, key: &str) -> Option<&'a str> {
+    content.get(key).and_then(Value::as_str)
+}
+
+/// Power levels are integers, but older room versions also accept strings.
+fn power_level(value: Option<&Value>) -> Option<i64> {
+    match value? {
+        Value::Number(n) => n.as_i64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
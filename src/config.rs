@@ -0,0 +1,383 @@
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::error::FuzzError;
+
+const DEFAULT_CONFIG_PATH: &str = "matrix-fuzz.toml";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FuzzConfig {
+    pub server: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Secret of the shared secret auth module, used with the `token_auth` feature.
+    pub shared_secret: Option<String>,
+    /// `registration_shared_secret` of Synapse. If set, missing users are created on the fly.
+    pub registration_shared_secret: Option<String>,
+    /// Server name used to build the fuzzer's user ID. Defaults to the host of `server`.
+    pub server_name: Option<String>,
+    pub timeouts: Timeouts,
+    /// Targets to fuzz. An empty list enables every target.
+    pub targets: Vec<String>,
+    /// Errors which are not considered a finding. See `allowlist.toml` for the format.
+    pub allowlist_file: PathBuf,
+    pub output: OutputDirs,
+    pub campaign: Campaign,
+    pub identities: Identities,
+    pub latency: Latency,
+    pub server_log: ServerLog,
+    pub metrics: Metrics,
+    pub server_coverage: ServerCoverage,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Timeouts {
+    pub connect_secs: u64,
+    /// Time a request may take in total, from connecting to reading the whole response.
+    pub request_secs: u64,
+    /// Deadlines of fuzzed requests by target name, replacing `request_secs`. A request missing
+    /// its deadline is a hang.
+    pub deadlines: HashMap<String, u64>,
+    /// Time the server gets to answer the liveness probe after a hang.
+    pub liveness_secs: u64,
+    /// How often the fuzzer's own requests, like logging in, are retried on transient errors.
+    pub retries: u32,
+    /// Delay before the first retry. Doubles with every further retry.
+    pub retry_backoff_ms: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct OutputDirs {
+    pub findings: PathBuf,
+    pub corpus: PathBuf,
+}
+
+/// How long a fuzzcheck campaign runs.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Campaign {
+    /// Keep fuzzing after a new finding instead of stopping at the first one.
+    pub continue_on_failure: bool,
+    /// Stop after this many seconds of fuzzing.
+    pub max_duration_secs: Option<u64>,
+    /// Stop after this many executed inputs.
+    pub max_iterations: Option<usize>,
+}
+
+/// The pool of users checking authorization invariants, see `identities::Pool`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Identities {
+    /// Check authorization invariants with other users after successful requests. Needs
+    /// `registration_shared_secret` to create the users.
+    pub enabled: bool,
+}
+
+/// Detection of slow responses, see `latency`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Latency {
+    /// Compare the response time of every fuzzed input with the target's baseline.
+    pub enabled: bool,
+    /// How many recent response times make up a target's baseline.
+    pub window: usize,
+    /// Response times needed before a target's baseline is used.
+    pub min_samples: usize,
+    /// How many robust standard deviations above the median a response time is an outlier.
+    pub threshold: f64,
+    /// Minimum distance from the median for an outlier, so fast and steady targets do not flag
+    /// every hiccup.
+    pub min_excess_ms: u64,
+    /// How often an outlier is resent. Only if every resend is an outlier too it is a finding.
+    pub confirmations: u32,
+    /// Delay the injected payloads ask for.
+    pub sleep_secs: u64,
+}
+
+/// The homeserver log the fuzzer watches for errors, see `server_log`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ServerLog {
+    /// Log file of the homeserver. Unset disables watching the log.
+    pub path: Option<PathBuf>,
+    /// How long to wait after a response for the server to finish logging about the request.
+    pub settle_ms: u64,
+}
+
+/// Scraping of the homeserver's Prometheus metrics, see `metrics`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Metrics {
+    /// Metrics endpoint of the homeserver. Unset disables scraping.
+    pub url: Option<String>,
+    /// Prefixes of the series whose changes count as coverage for fuzzcheck.
+    pub feedback: Vec<String>,
+    /// Series which must not grow by more than a limit with every request.
+    pub growth: Vec<GrowthLimit>,
+    /// How often an input exceeding a growth limit is resent. Only if the series exceeds the
+    /// limit every time it is a finding.
+    pub confirmations: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GrowthLimit {
+    /// Prefix of the series, e.g. a metric name without labels.
+    pub prefix: String,
+    pub max_delta: f64,
+}
+
+/// Coverage of a Synapse run under `dockerfiles/synapse_coverage.py`, see `server_coverage`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ServerCoverage {
+    /// Side channel serving the arcs Synapse executed since the last fetch. Unset disables
+    /// fetching coverage.
+    pub url: Option<String>,
+}
+
+impl Campaign {
+    pub fn max_duration(&self) -> Option<Duration> {
+        self.max_duration_secs.map(Duration::from_secs)
+    }
+}
+
+impl Default for FuzzConfig {
+    fn default() -> Self {
+        FuzzConfig {
+            server: "http://localhost:8008".to_string(),
+            username: None,
+            password: None,
+            shared_secret: None,
+            registration_shared_secret: None,
+            server_name: None,
+            timeouts: Timeouts::default(),
+            targets: Vec::new(),
+            allowlist_file: PathBuf::from("./allowlist.toml"),
+            output: OutputDirs::default(),
+            campaign: Campaign::default(),
+            identities: Identities::default(),
+            latency: Latency::default(),
+            server_log: ServerLog::default(),
+            metrics: Metrics::default(),
+            server_coverage: ServerCoverage::default(),
+        }
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            url: None,
+            feedback: vec![
+                "synapse_storage_transaction_time_count".to_string(),
+                "synapse_util_caches_cache".to_string(),
+                "synapse_background_process".to_string(),
+                "process_resident_memory_bytes".to_string(),
+            ],
+            growth: vec![
+                GrowthLimit {
+                    prefix: "process_resident_memory_bytes".to_string(),
+                    max_delta: 16.0 * 1024.0 * 1024.0,
+                },
+                GrowthLimit {
+                    prefix: "synapse_storage_events_persisted_events".to_string(),
+                    max_delta: 50.0,
+                },
+            ],
+            confirmations: 2,
+        }
+    }
+}
+
+impl Default for ServerLog {
+    fn default() -> Self {
+        ServerLog {
+            path: None,
+            settle_ms: 100,
+        }
+    }
+}
+
+impl ServerLog {
+    pub fn settle(&self) -> Duration {
+        Duration::from_millis(self.settle_ms)
+    }
+}
+
+impl Default for Latency {
+    fn default() -> Self {
+        Latency {
+            enabled: false,
+            window: 200,
+            min_samples: 20,
+            threshold: 6.0,
+            min_excess_ms: 1000,
+            confirmations: 2,
+            sleep_secs: 5,
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            connect_secs: 30,
+            request_secs: 30,
+            deadlines: HashMap::new(),
+            liveness_secs: 10,
+            retries: 5,
+            retry_backoff_ms: 500,
+        }
+    }
+}
+
+impl Default for OutputDirs {
+    fn default() -> Self {
+        OutputDirs {
+            findings: PathBuf::from("./findings"),
+            corpus: PathBuf::from("./corpus"),
+        }
+    }
+}
+
+impl FuzzConfig {
+    /// Loads the config file named by `$MATRIX_FUZZ_CONFIG` (or `./matrix-fuzz.toml` if it
+    /// exists) and applies the `$MATRIX_SERVER`, `$MATRIX_USERNAME`, `$MATRIX_PASSWORD`,
+    /// `$MATRIX_SHARED_SECRET`, `$MATRIX_REGISTRATION_SHARED_SECRET` and `$MATRIX_SERVER_NAME`
+    /// overrides on top.
+    pub fn load() -> Result<FuzzConfig, FuzzError> {
+        let mut config = match env::var("MATRIX_FUZZ_CONFIG") {
+            Ok(path) => FuzzConfig::from_file(Path::new(&path))?,
+            Err(_) if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                FuzzConfig::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            Err(_) => FuzzConfig::default(),
+        };
+        config.apply_env();
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<FuzzConfig, FuzzError> {
+        let error = |reason: String| FuzzError::Config {
+            path: path.to_path_buf(),
+            reason,
+        };
+        let content = fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+        toml::from_str(&content).map_err(|e| error(e.to_string()))
+    }
+
+    fn apply_env(&mut self) {
+        if let Ok(v) = env::var("MATRIX_SERVER") {
+            self.server = v;
+        }
+        if let Ok(v) = env::var("MATRIX_USERNAME") {
+            self.username = Some(v);
+        }
+        if let Ok(v) = env::var("MATRIX_PASSWORD") {
+            self.password = Some(v);
+        }
+        if let Ok(v) = env::var("MATRIX_SHARED_SECRET") {
+            self.shared_secret = Some(v);
+        }
+        if let Ok(v) = env::var("MATRIX_REGISTRATION_SHARED_SECRET") {
+            self.registration_shared_secret = Some(v);
+        }
+        if let Ok(v) = env::var("MATRIX_SERVER_NAME") {
+            self.server_name = Some(v);
+        }
+        while self.server.ends_with('/') {
+            self.server.pop();
+        }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.server, path)
+    }
+
+    pub fn username(&self) -> Result<&str, FuzzError> {
+        self.username.as_deref().ok_or(FuzzError::MissingSetting {
+            setting: "username",
+            env: "MATRIX_USERNAME",
+        })
+    }
+
+    pub fn password(&self) -> Result<&str, FuzzError> {
+        self.password.as_deref().ok_or(FuzzError::MissingSetting {
+            setting: "password",
+            env: "MATRIX_PASSWORD",
+        })
+    }
+
+    pub fn shared_secret(&self) -> Result<&str, FuzzError> {
+        self.shared_secret
+            .as_deref()
+            .ok_or(FuzzError::MissingSetting {
+                setting: "shared_secret",
+                env: "MATRIX_SHARED_SECRET",
+            })
+    }
+
+    pub fn registration_shared_secret(&self) -> Result<&str, FuzzError> {
+        self.registration_shared_secret
+            .as_deref()
+            .ok_or(FuzzError::MissingSetting {
+                setting: "registration_shared_secret",
+                env: "MATRIX_REGISTRATION_SHARED_SECRET",
+            })
+    }
+
+    /// The fuzzer's full user ID. `username` may already be one, otherwise it is the localpart.
+    pub fn user_id(&self) -> Result<String, FuzzError> {
+        let username = self.username()?;
+        if username.starts_with('@') {
+            return Ok(username.to_string());
+        }
+        let server_name = match &self.server_name {
+            Some(v) => v.clone(),
+            None => reqwest::Url::parse(&self.server)
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_string))
+                .ok_or(FuzzError::MissingSetting {
+                    setting: "server_name",
+                    env: "MATRIX_SERVER_NAME",
+                })?,
+        };
+        Ok(format!("@{}:{}", username, server_name))
+    }
+
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.timeouts.connect_secs)
+    }
+
+    pub fn request_timeout(&self) -> Duration {
+        Duration::from_secs(self.timeouts.request_secs)
+    }
+
+    /// The deadline of a fuzzed request to `target`.
+    pub fn deadline(&self, target: &str) -> Duration {
+        match self.timeouts.deadlines.get(target) {
+            Some(secs) => Duration::from_secs(*secs),
+            None => self.request_timeout(),
+        }
+    }
+
+    pub fn liveness_timeout(&self) -> Duration {
+        Duration::from_secs(self.timeouts.liveness_secs)
+    }
+
+    pub fn retry_backoff(&self) -> Duration {
+        Duration::from_millis(self.timeouts.retry_backoff_ms)
+    }
+
+    pub fn target_enabled(&self, name: &str) -> bool {
+        self.targets.is_empty() || self.targets.iter().any(|t| t == name)
+    }
+}
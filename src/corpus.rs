@@ -0,0 +1,235 @@
+use serde_json::{Map, Value};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::targets::RegisteredTarget;
+
+/// A change of a target's input type which orphaned existing corpora.
+///
+/// Corpus files do not carry a schema version, so each migration recognises inputs of the old
+/// schema by their shape.
+pub struct Migration {
+    pub target: &'static str,
+    pub from: u32,
+    pub to: u32,
+    pub description: &'static str,
+    pub detect: fn(&Value) -> bool,
+    pub migrate: fn(Value) -> Value,
+}
+
+pub const MIGRATIONS: &[Migration] = &[Migration {
+    target: "createRoom",
+    from: 0,
+    to: 1,
+    description: "JSON objects were generated as `*_keys`/`*_values` lists and `room_version` \
+                  was fuzzed",
+    detect: is_create_room_v0,
+    migrate: migrate_create_room_v0,
+}];
+
+/// Layout of a corpus directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One JSON serialised input per `<hash>.json` file, as written by fuzzcheck's serde
+    /// serializer.
+    Fuzzcheck,
+    /// One input per file, as read by AFL and libFuzzer. The AFL binaries accept JSON bodies, so
+    /// inputs are written as JSON as well.
+    Afl,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub converted: usize,
+    pub migrated: usize,
+    /// Inputs from raw bytes which were decoded through the target's `Arbitrary` impl.
+    pub decoded: usize,
+    pub skipped: Vec<(PathBuf, String)>,
+}
+
+/// Applies every migration matching `value` in order.
+///
+/// Returns the migrated value and whether anything changed.
+pub fn migrate(target: &str, mut value: Value) -> (Value, bool) {
+    let mut migrated = false;
+    for migration in MIGRATIONS.iter().filter(|m| m.target == target) {
+        if (migration.detect)(&value) {
+            value = (migration.migrate)(value);
+            migrated = true;
+        }
+    }
+    (value, migrated)
+}
+
+/// Converts every file in `input` to the current input type of `target` and writes it to
+/// `output` in `format`.
+///
+/// JSON inputs are migrated from older schemas first. Anything else is only converted with
+/// `decode_bytes`, which treats it as raw AFL/libFuzzer bytes and decodes it the same way the AFL
+/// binary does, and skipped otherwise. Decoding an old seed which merely is not valid JSON
+/// yields an unrelated input, so it is only meant for corpora written by a fuzzer, like an AFL
+/// queue.
+pub fn convert(
+    target: &RegisteredTarget,
+    input: &Path,
+    output: &Path,
+    format: Format,
+    decode_bytes: bool,
+) -> io::Result<Report> {
+    fs::create_dir_all(output)?;
+    let mut report = Report::default();
+    for entry in fs::read_dir(input)? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+        let data = fs::read(&path)?;
+        let value = match serde_json::from_slice::<Value>(&data) {
+            Ok(value) => {
+                let (value, migrated) = migrate(target.name, value);
+                match (target.normalise)(value) {
+                    Ok(v) => {
+                        if migrated {
+                            report.migrated += 1;
+                        }
+                        v
+                    }
+                    Err(e) => {
+                        report.skipped.push((path, e));
+                        continue;
+                    }
+                }
+            }
+            Err(e) if !decode_bytes => {
+                report.skipped.push((path, format!("not JSON ({})", e)));
+                continue;
+            }
+            Err(_) => match (target.from_bytes)(&data) {
+                Some(v) => {
+                    report.decoded += 1;
+                    v
+                }
+                None => {
+                    report.skipped.push((path, "not enough data".to_string()));
+                    continue;
+                }
+            },
+        };
+
+        let json = serde_json::to_vec(&value)?;
+        let name = match format {
+            Format::Fuzzcheck => format!("{:016x}.json", stable_hash(&json)),
+            Format::Afl => format!("seed-{:016x}", stable_hash(&json)),
+        };
+        fs::write(output.join(name), json)?;
+        report.converted += 1;
+    }
+    Ok(report)
+}
+
+/// FNV-1a. Unlike `DefaultHasher` the result is stable across Rust versions, so it can be used
+/// for file names.
+pub fn stable_hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+fn is_create_room_v0(value: &Value) -> bool {
+    value.get("creation_content_keys").is_some()
+        || value.get("creation_content_values").is_some()
+        || value
+            .get("initial_state")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .any(|state| {
+                state.get("content_keys").is_some() || state.get("content_values").is_some()
+            })
+}
+
+fn migrate_create_room_v0(mut value: Value) -> Value {
+    if let Some(room) = value.as_object_mut() {
+        let keys = room.remove("creation_content_keys");
+        let values = room.remove("creation_content_values");
+        if keys.is_some() || values.is_some() {
+            room.insert("creation_content".to_string(), zip_object(keys, values));
+        }
+        room.remove("room_version");
+        if let Some(Value::Array(states)) = room.get_mut("initial_state") {
+            for state in states.iter_mut().filter_map(Value::as_object_mut) {
+                let keys = state.remove("content_keys");
+                let values = state.remove("content_values");
+                if keys.is_some() || values.is_some() {
+                    state.insert("content".to_string(), zip_object(keys, values));
+                }
+            }
+        }
+    }
+    value
+}
+
+/// Builds an object from parallel key and value lists. Surplus keys or values are dropped.
+fn zip_object(keys: Option<Value>, values: Option<Value>) -> Value {
+    let keys = match keys {
+        Some(Value::Array(v)) => v,
+        _ => Vec::new(),
+    };
+    let values = match values {
+        Some(Value::Array(v)) => v,
+        _ => Vec::new(),
+    };
+    let mut object = Map::new();
+    for (key, value) in keys.into_iter().zip(values) {
+        if let Value::String(key) = key {
+            object.insert(key, value);
+        }
+    }
+    Value::Object(object)
+}
+
+#[cfg(all(test, not(fuzzing)))]
+mod tests {
+    use serde_json::json;
+
+    use super::migrate;
+
+    #[test]
+    fn migrates_create_room_v0() {
+        let old = json!({
+            "creation_content_keys": ["a", "b"],
+            "creation_content_values": ["1"],
+            "initial_state": [{
+                "content_keys": ["k"],
+                "content_values": ["v", "w"],
+                "type": "m.room.topic",
+                "state_key": "",
+            }],
+            "room_version": "9",
+            "topic": "t",
+        });
+
+        let (new, migrated) = migrate("createRoom", old);
+        assert!(migrated);
+        assert_eq!(
+            new,
+            json!({
+                "creation_content": {"a": "1"},
+                "initial_state": [{
+                    "content": {"k": "v"},
+                    "type": "m.room.topic",
+                    "state_key": "",
+                }],
+                "topic": "t",
+            })
+        );
+
+        let (_, migrated) = migrate("createRoom", new);
+        assert!(!migrated);
+    }
+}
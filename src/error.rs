@@ -0,0 +1,111 @@
+use std::{error::Error, fmt, path::PathBuf, thread, time::Duration};
+
+/// Errors of the fuzzer's own requests, as opposed to findings in the fuzzed server.
+#[derive(Debug)]
+pub enum FuzzError {
+    /// The config file or a data file it refers to, like the allowlist, could not be read or
+    /// parsed.
+    Config { path: PathBuf, reason: String },
+    /// A setting needed for the current operation is not configured.
+    MissingSetting {
+        setting: &'static str,
+        env: &'static str,
+    },
+    /// The HTTP client could not be built.
+    Client(reqwest::Error),
+    /// The request could not be sent or the response not be read.
+    Network { url: String, source: reqwest::Error },
+    /// The server answered with an error status.
+    Status {
+        url: String,
+        status: u16,
+        body: String,
+    },
+    /// The response body did not have the expected shape.
+    Decode {
+        url: String,
+        source: serde_json::Error,
+    },
+    /// The server does not offer the login flow the fuzzer logs in with.
+    LoginFlowUnavailable {
+        wanted: &'static str,
+        offered: Vec<String>,
+    },
+}
+
+impl FuzzError {
+    /// Whether retrying the same request may succeed, e.g. because the server is restarting.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            FuzzError::Network { source, .. } => source.is_connect() || source.is_timeout(),
+            FuzzError::Status { status, .. } => matches!(status, 429 | 502 | 503 | 504),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for FuzzError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FuzzError::Config { path, reason } => {
+                write!(f, "Unable to load config {} ({})", path.display(), reason)
+            }
+            FuzzError::MissingSetting { setting, env } => {
+                write!(
+                    f,
+                    "No {} configured (set `{}` or ${})",
+                    setting, setting, env
+                )
+            }
+            FuzzError::Client(e) => write!(f, "Unable to build the HTTP client ({})", e),
+            FuzzError::Network { url, source } => {
+                write!(f, "Request to {} failed ({})", url, source)
+            }
+            FuzzError::Status { url, status, body } => {
+                write!(f, "{} answered with {}: {}", url, status, body)
+            }
+            FuzzError::Decode { url, source } => {
+                write!(f, "Unexpected response from {} ({})", url, source)
+            }
+            FuzzError::LoginFlowUnavailable { wanted, offered } => write!(
+                f,
+                "The server does not offer {} login, it offers [{}]",
+                wanted,
+                offered.join(", ")
+            ),
+        }
+    }
+}
+
+impl Error for FuzzError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FuzzError::Client(e) | FuzzError::Network { source: e, .. } => Some(e),
+            FuzzError::Decode { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Runs `f` until it succeeds, it fails with an error which is not transient or `retries`
+/// retries are used up. The delay between attempts starts at `backoff` and doubles each time.
+#[no_coverage]
+pub fn retry<T>(
+    retries: u32,
+    backoff: Duration,
+    mut f: impl FnMut() -> Result<T, FuzzError>,
+) -> Result<T, FuzzError> {
+    let mut delay = backoff;
+    let mut attempt = 0;
+    loop {
+        match f() {
+            Err(e) if e.is_transient() && attempt < retries => {
+                println!("{}, retrying in {:?}", e, delay);
+                thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
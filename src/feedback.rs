@@ -0,0 +1,203 @@
+use once_cell::sync::OnceCell;
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex, PoisonError,
+};
+
+use crate::{corpus::stable_hash, findings::normalise_message, oracle::MatrixError};
+
+pub mod sensor;
+
+/// Kinds of server behaviour fed back to fuzzcheck. The channel is the index of a feature in
+/// the observations of [`sensor::ServerSensor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    /// Changes of the homeserver's Prometheus metrics, see `metrics`.
+    Metrics,
+    /// Classes of the responses to the fuzzer's requests, see [`response_class`].
+    Responses,
+    /// Lines of Synapse executed for the fuzzer's requests, see `server_coverage`.
+    PythonCoverage,
+}
+
+impl Channel {
+    pub const COUNT: usize = 3;
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+static RECORDING: AtomicBool = AtomicBool::new(false);
+
+fn features() -> &'static Mutex<Vec<(usize, u64)>> {
+    static INSTANCE: OnceCell<Mutex<Vec<(usize, u64)>>> = OnceCell::new();
+    INSTANCE.get_or_init(Default::default)
+}
+
+/// Records that the current input made the server show `feature`, e.g. a hash of a metric
+/// change. Inputs showing a feature no earlier input showed are kept in the corpus.
+///
+/// Does nothing unless fuzzcheck is recording, so the AFL binaries and the CLI do not collect
+/// features nobody reads.
+#[no_coverage]
+pub fn observe(channel: Channel, feature: u64) {
+    if recording() {
+        features()
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((channel.index(), feature));
+    }
+}
+
+/// Whether fuzzcheck is running an input, so observed features are used.
+#[no_coverage]
+pub fn recording() -> bool {
+    RECORDING.load(Ordering::Relaxed)
+}
+
+/// Records the class of a response of `endpoint`, see [`response_class`].
+#[no_coverage]
+pub fn observe_response(endpoint: &str, status: StatusCode, content: &str) {
+    if recording() {
+        observe(
+            Channel::Responses,
+            response_class(endpoint, status, content),
+        );
+    }
+}
+
+/// Hash of the status, errcode, normalised error message and shape of a response. Responses of
+/// the same class most likely come from the same branch of the server, so an input getting a
+/// response of a new class reached validation code no earlier input did.
+pub fn response_class(endpoint: &str, status: StatusCode, content: &str) -> u64 {
+    let body = serde_json::from_str(content).ok();
+    let error = body
+        .as_ref()
+        .and_then(|body| MatrixError::from_body(status, body));
+    let (errcode, message) = match &error {
+        Some(error) => (
+            error.errcode.as_str(),
+            normalise_message(error.error.as_deref().unwrap_or_default()),
+        ),
+        None => ("", String::new()),
+    };
+    let shape = body.as_ref().map_or_else(|| "text".to_string(), shape);
+    let class = format!(
+        "{}\n{}\n{}\n{}\n{}",
+        endpoint,
+        status.as_u16(),
+        errcode,
+        message,
+        shape
+    );
+    stable_hash(class.as_bytes())
+}
+
+/// The top-level keys of a JSON body and the types of their values, e.g.
+/// `{room_alias:string,room_id:string}`.
+fn shape(body: &Value) -> String {
+    match body {
+        Value::Object(fields) => {
+            let mut keys: Vec<String> = fields
+                .iter()
+                .map(|(key, value)| format!("{}:{}", key, type_name(value)))
+                .collect();
+            keys.sort_unstable();
+            format!("{{{}}}", keys.join(","))
+        }
+        value => type_name(value).to_string(),
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[no_coverage]
+fn start() {
+    features()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clear();
+    RECORDING.store(true, Ordering::Relaxed);
+}
+
+#[no_coverage]
+fn stop() {
+    RECORDING.store(false, Ordering::Relaxed);
+}
+
+#[no_coverage]
+fn take() -> Vec<(usize, u64)> {
+    let mut features =
+        std::mem::take(&mut *features().lock().unwrap_or_else(PoisonError::into_inner));
+    features.sort_unstable();
+    features.dedup();
+    features
+}
+
+#[cfg(all(test, not(fuzzing)))]
+mod tests {
+    use reqwest::StatusCode;
+
+    use super::response_class;
+
+    #[test]
+    fn classifies_responses() {
+        let class = |status: u16, content: &str| {
+            response_class(
+                "create_room",
+                StatusCode::from_u16(status).unwrap(),
+                content,
+            )
+        };
+        let created = class(200, r#"{"room_id": "!abc:localhost"}"#);
+        assert_eq!(created, class(200, r#"{"room_id": "!def:localhost"}"#));
+        assert_ne!(
+            created,
+            class(200, r#"{"room_id": "!abc:localhost", "room_alias": "a:b"}"#)
+        );
+        assert_ne!(created, class(200, r#"{"room_id": 1}"#));
+
+        // Only the IDs and numbers in a message do not matter
+        let invalid = class(
+            400,
+            r#"{"errcode": "M_INVALID_PARAM", "error": "Invalid user @a:b"}"#,
+        );
+        assert_eq!(
+            invalid,
+            class(
+                400,
+                r#"{"errcode": "M_INVALID_PARAM", "error": "Invalid user @c:d"}"#
+            )
+        );
+        assert_ne!(
+            invalid,
+            class(
+                400,
+                r#"{"errcode": "M_INVALID_PARAM", "error": "Invalid room !a:b"}"#
+            )
+        );
+        assert_ne!(
+            invalid,
+            class(
+                403,
+                r#"{"errcode": "M_INVALID_PARAM", "error": "Invalid user @a:b"}"#
+            )
+        );
+        assert_ne!(
+            class(500, "Internal Server Error"),
+            class(502, "Internal Server Error")
+        );
+    }
+}
@@ -0,0 +1,45 @@
+use fuzzcheck::{sensors_and_pools::UniqueValuesPool, SaveToStatsFolder, Sensor};
+use std::path::PathBuf;
+
+use super::Channel;
+
+/// Reports the features observed with [`super::observe`] while fuzzcheck runs an input.
+///
+/// Fuzzcheck only sees the coverage of the fuzzer itself, which is about the same for every
+/// input of a network fuzzer. Combined with [`pool`] the server's behaviour decides which
+/// inputs are worth keeping instead.
+#[derive(Default)]
+pub struct ServerSensor;
+
+impl SaveToStatsFolder for ServerSensor {
+    #[no_coverage]
+    fn save_to_stats_folder(&self) -> Vec<(PathBuf, Vec<u8>)> {
+        Vec::new()
+    }
+}
+
+impl Sensor for ServerSensor {
+    /// Pairs of [`Channel::index`] and feature.
+    type Observations = Vec<(usize, u64)>;
+
+    #[no_coverage]
+    fn start_recording(&mut self) {
+        super::start();
+    }
+
+    #[no_coverage]
+    fn stop_recording(&mut self) {
+        super::stop();
+    }
+
+    #[no_coverage]
+    fn get_observations(&mut self) -> Self::Observations {
+        super::take()
+    }
+}
+
+/// Keeps the simplest input for every feature of every channel.
+#[no_coverage]
+pub fn pool() -> UniqueValuesPool {
+    UniqueValuesPool::new("server", Channel::COUNT)
+}
@@ -0,0 +1,383 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{corpus::stable_hash, oracle::Verdict, replay};
+
+/// Name of the file describing a bucket, next to the inputs in the bucket directory.
+pub const BUCKET_FILE: &str = "bucket.json";
+
+/// Suffix of the file next to a saved input which records the exchange that caused the finding.
+pub const RECORD_SUFFIX: &str = ".finding.json";
+
+/// Inputs kept per bucket. Further instances only increase the count.
+pub const MAX_EXAMPLES: u64 = 10;
+
+/// Longest normalised message used in a signature, in chars.
+const MAX_MESSAGE_LEN: usize = 200;
+
+/// Identifies a bug independent of the input which triggered it.
+///
+/// Findings with the same signature are most likely instances of the same bug, so they are
+/// stored in the same bucket and only the first one is reported.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Signature {
+    pub target: String,
+    pub kind: String,
+    pub status: Option<u16>,
+    pub errcode: Option<String>,
+    /// The error message with IDs and numbers replaced by placeholders.
+    pub message: String,
+    /// Innermost frame of a traceback in the response, if the server sent one.
+    pub frame: Option<String>,
+}
+
+impl Signature {
+    /// Known bug labels are ignored, so a bug keeps its bucket when it is added to or expires
+    /// from the known bug list.
+    pub fn of(target: &str, verdict: &Verdict) -> Signature {
+        let verdict = verdict.unlabelled();
+        let (errcode, message) = match (verdict.error(), verdict.content(), verdict) {
+            // The response is whatever the offending request returns, the invariant is the bug
+            (_, _, Verdict::InvariantViolation { invariant, .. }) => (None, invariant.clone()),
+            (_, _, Verdict::LoggedError { error, .. }) => (None, error.clone()),
+            (_, _, Verdict::ResourceGrowth { series, .. }) => (None, series.clone()),
+            // A request hanging and one taking down the server are different bugs
+            (_, _, Verdict::Hang { server_alive, .. }) => {
+                let message = if *server_alive {
+                    "slow request"
+                } else {
+                    "server wedged"
+                };
+                (None, message.to_string())
+            }
+            (Some(error), _, _) => (
+                Some(error.errcode.clone()),
+                error.error.clone().unwrap_or_default(),
+            ),
+            (None, Some(content), _) => body_message(content),
+            (None, None, Verdict::TransportError(e)) => (None, e.clone()),
+            (None, None, _) => (None, String::new()),
+        };
+        Signature {
+            target: target.to_string(),
+            kind: verdict.kind().to_string(),
+            status: verdict.status(),
+            errcode,
+            message: normalise_message(&message),
+            frame: verdict.content().and_then(traceback_frame),
+        }
+    }
+
+    /// Stable name of the bucket, used as its directory name.
+    pub fn id(&self) -> String {
+        format!("{:016x}", stable_hash(&serde_json::to_vec(self).unwrap()))
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(status) = self.status {
+            write!(f, " {}", status)?;
+        }
+        if let Some(errcode) = &self.errcode {
+            write!(f, " {}", errcode)?;
+        }
+        if !self.message.is_empty() {
+            write!(f, ": {}", self.message)?;
+        }
+        if let Some(frame) = &self.frame {
+            write!(f, " (at {})", frame)?;
+        }
+        Ok(())
+    }
+}
+
+/// A group of findings sharing a signature, as stored in [`BUCKET_FILE`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bucket {
+    pub signature: Signature,
+    /// How often the bug was hit, including inputs which were not kept.
+    pub count: u64,
+    /// The verdict of the first finding.
+    pub verdict: String,
+}
+
+/// A request as sent to the server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Request {
+    pub target: String,
+    pub method: String,
+    pub path: String,
+    pub body: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Response {
+    pub status: Option<u16>,
+    pub body: Option<String>,
+}
+
+/// Everything known about a single finding, as stored in the [`RECORD_SUFFIX`] file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub request: Request,
+    pub response: Response,
+    pub verdict: String,
+}
+
+impl Record {
+    pub fn new(request: &Request, verdict: &Verdict) -> Record {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        // Rejections only keep the parsed error, which serialises back to the same fields
+        let body = match verdict.error() {
+            Some(error) => {
+                let mut body = error.extra.clone();
+                body.insert("errcode".to_string(), Value::from(error.errcode.clone()));
+                if let Some(message) = &error.error {
+                    body.insert("error".to_string(), Value::from(message.clone()));
+                }
+                Some(Value::Object(body).to_string())
+            }
+            None => verdict.content().map(str::to_string),
+        };
+        Record {
+            timestamp,
+            request: request.clone(),
+            response: Response {
+                status: verdict.status(),
+                body,
+            },
+            verdict: verdict.to_string(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Recorded {
+    pub id: String,
+    pub path: PathBuf,
+    /// Whether this is the first finding in the bucket.
+    pub new: bool,
+}
+
+pub fn bucket_path(dir: &Path, signature: &Signature) -> PathBuf {
+    dir.join(&signature.target).join(signature.id())
+}
+
+pub fn read_bucket(path: &Path) -> Option<Bucket> {
+    let content = fs::read(path.join(BUCKET_FILE)).ok()?;
+    serde_json::from_slice(&content).ok()
+}
+
+pub fn record_path(input: &Path) -> PathBuf {
+    let mut name = input.file_name().unwrap_or_default().to_os_string();
+    name.push(RECORD_SUFFIX);
+    input.with_file_name(name)
+}
+
+/// Stores a finding in its bucket below `dir`.
+///
+/// The request body is saved with the verdict as expectation, so a bucket directory can be
+/// passed to `matrix-fuzz replay` directly. The full request and response are kept next to it
+/// in a [`Record`].
+pub fn record(dir: &Path, request: &Request, verdict: &Verdict) -> io::Result<Recorded> {
+    let signature = Signature::of(&request.target, verdict);
+    let id = signature.id();
+    let path = bucket_path(dir, &signature);
+    let mut bucket = read_bucket(&path).unwrap_or_else(|| Bucket {
+        signature,
+        count: 0,
+        verdict: verdict.to_string(),
+    });
+    let new = bucket.count == 0;
+    bucket.count += 1;
+
+    fs::create_dir_all(&path)?;
+    if bucket.count <= MAX_EXAMPLES {
+        let json = serde_json::to_vec_pretty(&request.body)?;
+        let input = path.join(format!("{:016x}.json", stable_hash(&json)));
+        fs::write(&input, json)?;
+        replay::write_expectation(&input, verdict)?;
+        let record = Record::new(request, verdict);
+        fs::write(record_path(&input), serde_json::to_vec_pretty(&record)?)?;
+    }
+    fs::write(path.join(BUCKET_FILE), serde_json::to_vec_pretty(&bucket)?)?;
+    Ok(Recorded { id, path, new })
+}
+
+/// Lists the buckets of `target` below `dir`, most frequent first.
+pub fn buckets(dir: &Path, target: &str) -> io::Result<Vec<(PathBuf, Bucket)>> {
+    let mut buckets = Vec::new();
+    let dir = dir.join(target);
+    if !dir.exists() {
+        return Ok(buckets);
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if let Some(bucket) = read_bucket(&path) {
+            buckets.push((path, bucket));
+        }
+    }
+    buckets.sort_by(|(a_path, a), (b_path, b)| b.count.cmp(&a.count).then(a_path.cmp(b_path)));
+    Ok(buckets)
+}
+
+/// Extracts errcode and message from a response body which is not a client error, e.g. the
+/// `{"errcode": "M_UNKNOWN", "error": "Internal server error"}` of a 500.
+fn body_message(content: &str) -> (Option<String>, String) {
+    if let Ok(body) = serde_json::from_str::<Value>(content) {
+        let errcode = body.get("errcode").and_then(Value::as_str);
+        let error = body.get("error").and_then(Value::as_str);
+        if errcode.is_some() || error.is_some() {
+            return (
+                errcode.map(str::to_string),
+                error.unwrap_or_default().to_string(),
+            );
+        }
+    }
+    (None, content.lines().next().unwrap_or_default().to_string())
+}
+
+/// Replaces the parts of a message which differ between instances of the same bug.
+///
+/// Matrix IDs (`@user:server`, `!room:server`, ...) and long hex or base64 tokens become
+/// `<id>`, any other run of digits becomes `<n>`. Whitespace is collapsed.
+pub fn normalise_message(message: &str) -> String {
+    let mut normalised = String::new();
+    let mut token = String::new();
+    for c in message.chars().chain(Some(' ')) {
+        if c.is_whitespace() || "\"'`,;()[]{}<>=".contains(c) {
+            normalised.push_str(&normalise_token(&token));
+            token.clear();
+            if !c.is_whitespace() {
+                normalised.push(c);
+            } else if !normalised.ends_with(' ') {
+                normalised.push(' ');
+            }
+        } else {
+            token.push(c);
+        }
+    }
+    normalised.trim().chars().take(MAX_MESSAGE_LEN).collect()
+}
+
+fn normalise_token(token: &str) -> String {
+    let mut chars = token.chars();
+    if let Some(sigil) = chars.next() {
+        if "!@#$+".contains(sigil) && chars.next().is_some() {
+            return "<id>".to_string();
+        }
+    }
+    if !token.chars().any(|c| c.is_ascii_digit()) {
+        return token.to_string();
+    }
+    if token.len() >= 16
+        && token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_+/".contains(c))
+    {
+        return "<id>".to_string();
+    }
+
+    let mut normalised = String::new();
+    for c in token.chars() {
+        if !c.is_ascii_digit() {
+            normalised.push(c);
+        } else if !normalised.ends_with("<n>") {
+            normalised.push_str("<n>");
+        }
+    }
+    normalised
+}
+
+/// Innermost frame of a Python traceback or the location of a Rust panic in `content`,
+/// without line numbers.
+pub fn traceback_frame(content: &str) -> Option<String> {
+    // Python: `  File "/synapse/handlers/room.py", line 123, in create_room`
+    let python = content
+        .lines()
+        .rev()
+        .filter_map(|line| line.trim().strip_prefix("File \""))
+        .find_map(|frame| {
+            let (file, rest) = frame.split_once('"')?;
+            let function = rest.rsplit_once(" in ").map_or("", |(_, f)| f.trim());
+            Some(format!("{} in {}", file, function))
+        });
+    if python.is_some() {
+        return python;
+    }
+
+    // Rust: `panicked at 'message', src/main.rs:10:5` or `panicked at src/main.rs:10:5:`
+    let (_, panic) = content.split_once("panicked at ")?;
+    panic
+        .split(|c: char| c.is_whitespace() || c == ',' || c == '\'')
+        .find_map(|token| {
+            let (file, _) = token.split_once(".rs:")?;
+            Some(format!("{}.rs", file))
+        })
+}
+
+#[cfg(all(test, not(fuzzing)))]
+mod tests {
+    use super::{normalise_message, traceback_frame, Signature};
+    use crate::oracle::Verdict;
+
+    #[test]
+    fn normalises_ids_and_numbers() {
+        assert_eq!(
+            normalise_message("User @fuzz:localhost  not in room !abcDEF:localhost (attempt 3)"),
+            "User <id> not in room <id> (attempt <n>)"
+        );
+
+        // The same bug hit with different rooms ends up in the same bucket
+        let a = Verdict::ServerError {
+            status: 500,
+            content: r#"{"errcode": "M_UNKNOWN", "error": "Failed on !a:localhost"}"#.to_string(),
+        };
+        let b = Verdict::ServerError {
+            status: 500,
+            content: r#"{"errcode": "M_UNKNOWN", "error": "Failed on !b:localhost"}"#.to_string(),
+        };
+        assert_eq!(
+            Signature::of("createRoom", &a).id(),
+            Signature::of("createRoom", &b).id()
+        );
+
+        // A slow request and a wedged server are different bugs, whatever the deadline
+        let hang = |deadline_ms, server_alive| {
+            Signature::of(
+                "createRoom",
+                &Verdict::Hang {
+                    deadline_ms,
+                    server_alive,
+                },
+            )
+            .id()
+        };
+        assert_eq!(hang(30_000, true), hang(10_000, true));
+        assert_ne!(hang(30_000, true), hang(30_000, false));
+    }
+
+    #[test]
+    fn extracts_innermost_frame() {
+        let traceback = "Traceback (most recent call last):\n  \
+            File \"/synapse/http/server.py\", line 306, in _async_render_wrapper\n  \
+            File \"/synapse/handlers/room.py\", line 912, in create_room\n\
+            ValueError: A string literal cannot contain NUL (0x00) characters.";
+        assert_eq!(
+            traceback_frame(traceback).as_deref(),
+            Some("/synapse/handlers/room.py in create_room")
+        );
+        assert_eq!(traceback_frame("Internal server error"), None);
+    }
+}
@@ -0,0 +1,260 @@
+use arbitrary::Arbitrary;
+use fuzzcheck::DefaultMutator;
+use hmac::{Hmac, Mac};
+use reqwest::{Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Mutex, PoisonError},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    error::FuzzError,
+    provision::{self, NewUser, Registered},
+    types::LoginPost,
+};
+
+/// The part a user plays towards the rooms the fuzzer creates.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, DefaultMutator, Arbitrary,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// The configured user, which creates the rooms.
+    Owner,
+    /// A user the owner invites into rooms, without any power.
+    Member,
+    /// A user which has nothing to do with the fuzzer's rooms.
+    Stranger,
+    Guest,
+    /// A server admin.
+    Admin,
+    /// A user which was deactivated after logging in. Its access token must not work anymore.
+    Deactivated,
+}
+
+impl Role {
+    pub const ALL: &'static [Role] = &[
+        Role::Owner,
+        Role::Member,
+        Role::Stranger,
+        Role::Guest,
+        Role::Admin,
+        Role::Deactivated,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Member => "member",
+            Role::Stranger => "stranger",
+            Role::Guest => "guest",
+            Role::Admin => "admin",
+            Role::Deactivated => "deactivated",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub role: Role,
+    pub user_id: String,
+    pub access_token: String,
+}
+
+/// Users of the different roles, created on first use.
+///
+/// The owner is the configured user and uses the session of [`crate::session()`]. All other
+/// users are created through shared secret registration, so a pool needs
+/// `registration_shared_secret`. Their localparts are derived from the configured username.
+#[derive(Default)]
+pub struct Pool {
+    identities: Mutex<HashMap<Role, Identity>>,
+}
+
+impl Pool {
+    #[no_coverage]
+    pub fn get(&self, role: Role) -> Result<Identity, FuzzError> {
+        let mut identities = self
+            .identities
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let identity = match identities.get(&role) {
+            Some(v) => v.clone(),
+            None => {
+                let identity = create(role)?;
+                identities.insert(role, identity.clone());
+                identity
+            }
+        };
+        // The owner's session may have been renewed since
+        if role == Role::Owner {
+            return Ok(Identity {
+                access_token: crate::access_token()?,
+                ..identity
+            });
+        }
+        Ok(identity)
+    }
+}
+
+/// Sends a request as `identity` and returns the response whatever its status, as the status
+/// is what authorization invariants are about. The whole exchange has to finish within
+/// `deadline`.
+#[no_coverage]
+pub fn send_as(
+    identity: &Identity,
+    method: Method,
+    path: &str,
+    body: Option<&Value>,
+    deadline: Duration,
+) -> Result<(StatusCode, String), FuzzError> {
+    let url = crate::config()?.url(path);
+    let mut request = crate::client()?
+        .request(method, &url)
+        .timeout(deadline)
+        .header("Authorization", format!("Bearer {}", identity.access_token));
+    if let Some(body) = body {
+        request = request.json(body);
+    }
+    let network = |source| FuzzError::Network {
+        url: url.clone(),
+        source,
+    };
+    let resp = request.send().map_err(network)?;
+    let status = resp.status();
+    let content = resp.text().map_err(network)?;
+    Ok((status, content))
+}
+
+#[no_coverage]
+fn create(role: Role) -> Result<Identity, FuzzError> {
+    let registered = match role {
+        Role::Owner => return owner(),
+        Role::Guest => {
+            let url = crate::config()?.url("/_matrix/client/v3/register?kind=guest");
+            crate::request_json::<Registered>(Method::POST, &url, Some(&json!({})))?
+        }
+        Role::Member | Role::Stranger | Role::Admin => {
+            register_or_login(&localpart(role.name())?, role == Role::Admin)?
+        }
+        Role::Deactivated => {
+            // A deactivated user can not log in again, so every run needs a fresh one
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            let localpart = localpart(&format!("{}-{}", role.name(), now))?;
+            let registered = register_or_login(&localpart, false)?;
+            deactivate(&registered, &localpart)?;
+            registered
+        }
+    };
+    Ok(Identity {
+        role,
+        user_id: registered.user_id,
+        access_token: registered.access_token,
+    })
+}
+
+/// The configured user. Its ID is asked from the server as `username` may be a localpart.
+#[no_coverage]
+fn owner() -> Result<Identity, FuzzError> {
+    let mut identity = Identity {
+        role: Role::Owner,
+        user_id: String::new(),
+        access_token: crate::access_token()?,
+    };
+    let config = crate::config()?;
+    let path = "/_matrix/client/v3/account/whoami";
+    let (_, content) = send_as(&identity, Method::GET, path, None, config.request_timeout())?;
+    identity.user_id = match serde_json::from_str::<Value>(&content) {
+        Ok(Value::Object(whoami)) => match whoami.get("user_id") {
+            Some(Value::String(v)) => v.clone(),
+            _ => config.user_id()?,
+        },
+        _ => config.user_id()?,
+    };
+    Ok(identity)
+}
+
+/// Localpart of a pool user, e.g. `fuzzer-stranger`.
+fn localpart(suffix: &str) -> Result<String, FuzzError> {
+    let username = crate::config()?.username()?;
+    let username = username.trim_start_matches('@');
+    let username = username.split(':').next().unwrap_or(username);
+    Ok(format!("{}-{}", username, suffix))
+}
+
+/// Passwords of pool users are derived from the registration secret, so they survive restarts
+/// without being configured.
+fn password(localpart: &str) -> Result<String, FuzzError> {
+    let secret = crate::config()?.registration_shared_secret()?;
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(localpart.as_bytes());
+    Ok(hex::encode(mac.finalize().into_bytes()))
+}
+
+#[no_coverage]
+fn register_or_login(localpart: &str, admin: bool) -> Result<Registered, FuzzError> {
+    let password = password(localpart)?;
+    let user = NewUser {
+        username: localpart,
+        password: &password,
+        admin,
+        user_type: None,
+    };
+    match provision::register(&user) {
+        Err(FuzzError::Status { body, .. }) if body.contains("M_USER_IN_USE") => {}
+        res => return res,
+    }
+    let url = crate::config()?.url("/_matrix/client/v3/login");
+    let body = json!({
+        "type": "m.login.password",
+        "identifier": {"type": "m.id.user", "user": localpart},
+        "password": password,
+    });
+    let res: LoginPost = crate::request_json(Method::POST, &url, Some(&body))?;
+    Ok(Registered {
+        user_id: res.user_id,
+        access_token: res.access_token,
+    })
+}
+
+#[no_coverage]
+fn deactivate(registered: &Registered, localpart: &str) -> Result<(), FuzzError> {
+    let identity = Identity {
+        role: Role::Deactivated,
+        user_id: registered.user_id.clone(),
+        access_token: registered.access_token.clone(),
+    };
+    let body = json!({
+        "auth": {
+            "type": "m.login.password",
+            "identifier": {"type": "m.id.user", "user": localpart},
+            "password": password(localpart)?,
+        },
+    });
+    let config = crate::config()?;
+    let path = "/_matrix/client/v3/account/deactivate";
+    let timeout = config.request_timeout();
+    let (status, content) = send_as(&identity, Method::POST, path, Some(&body), timeout)?;
+    if !status.is_success() {
+        return Err(FuzzError::Status {
+            url: config.url(path),
+            status: status.as_u16(),
+            body: content,
+        });
+    }
+    Ok(())
+}
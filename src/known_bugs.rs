@@ -0,0 +1,221 @@
+use serde_json::Value;
+
+use crate::{
+    oracle::Verdict,
+    server::{ServerVersion, Version},
+};
+
+/// A bug which has already been reported upstream.
+///
+/// Inputs triggering a known bug are still generated and sent, but the result is labelled with
+/// the bug instead of being reported as a new finding. Once the server reports a version at or
+/// after `fixed_in` the suppression expires and the input is a finding again.
+pub struct KnownBug {
+    pub id: &'static str,
+    pub target: &'static str,
+    /// Homeserver implementation the bug exists in.
+    pub server: &'static str,
+    /// The upstream report. Every entry needs one, or the bug would be hidden without anyone
+    /// tracking a fix.
+    pub issue: &'static str,
+    /// First release containing the fix, if there is one.
+    pub fixed_in: Option<&'static str>,
+    /// Decides whether the sent request body and the verdict are an instance of the bug.
+    pub matches: fn(&Value, &Verdict) -> bool,
+}
+
+impl KnownBug {
+    /// Whether the bug can still occur on `server`. An unknown server version never expires a
+    /// bug.
+    pub fn applies_to(&self, server: Option<&ServerVersion>) -> bool {
+        let server = match server {
+            Some(v) => v,
+            None => return true,
+        };
+        if !server.is(self.server) {
+            return false;
+        }
+        match self.fixed_in {
+            Some(fixed_in) => server.parsed() < Version::parse(fixed_in),
+            None => true,
+        }
+    }
+}
+
+pub const KNOWN_BUGS: &[KnownBug] = &[
+    KnownBug {
+        id: "synapse-13510",
+        target: "createRoom",
+        server: "Synapse",
+        issue: "https://github.com/matrix-org/synapse/issues/13510",
+        fixed_in: None,
+        matches: |input, verdict| {
+            is_server_error(verdict) && str_contains_nul(input.get("room_alias_name"))
+        },
+    },
+    KnownBug {
+        id: "synapse-13511",
+        target: "createRoom",
+        server: "Synapse",
+        issue: "https://github.com/matrix-org/synapse/issues/13511",
+        fixed_in: None,
+        matches: |input, verdict| {
+            is_server_error(verdict)
+                && array(input, "invite_3pid")
+                    .any(|pid| pid.get("address") == Some(&Value::from("")))
+        },
+    },
+    KnownBug {
+        id: "synapse-13512",
+        target: "createRoom",
+        server: "Synapse",
+        issue: "https://github.com/matrix-org/synapse/issues/13512",
+        fixed_in: None,
+        // An `id_server` which is not a server name makes the identity server request fail with
+        // an unhandled error
+        matches: |input, verdict| {
+            is_unhandled_error(verdict) && array(input, "invite_3pid").any(has_invalid_id_server)
+        },
+    },
+];
+
+/// Returns the known bug `verdict` is an instance of, if any.
+pub fn find(
+    target: &str,
+    server: Option<&ServerVersion>,
+    input: &Value,
+    verdict: &Verdict,
+) -> Option<&'static KnownBug> {
+    KNOWN_BUGS
+        .iter()
+        .find(|bug| bug.target == target && bug.applies_to(server) && (bug.matches)(input, verdict))
+}
+
+fn is_server_error(verdict: &Verdict) -> bool {
+    matches!(verdict, Verdict::ServerError { .. })
+}
+
+/// A 500 with the generic `M_UNKNOWN` body Synapse sends for exceptions it did not handle.
+fn is_unhandled_error(verdict: &Verdict) -> bool {
+    match verdict {
+        Verdict::ServerError {
+            status: 500,
+            content,
+        } => {
+            let body: Value = serde_json::from_str(content).unwrap_or_default();
+            body.get("errcode") == Some(&Value::from("M_UNKNOWN"))
+        }
+        _ => false,
+    }
+}
+
+fn has_invalid_id_server(pid: &Value) -> bool {
+    matches!(pid.get("id_server").and_then(Value::as_str), Some(s) if !is_server_name(s))
+}
+
+/// Whether `s` is a hostname or IP literal with an optional port, like `matrix.org:8448`.
+fn is_server_name(s: &str) -> bool {
+    let host = match s.rsplit_once(':') {
+        Some((host, port)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => host,
+        _ => s,
+    };
+    if let Some(ip) = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+        return !ip.is_empty()
+            && ip
+                .chars()
+                .all(|c| c.is_ascii_hexdigit() || c == ':' || c == '.');
+    }
+    !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+}
+
+fn str_contains_nul(value: Option<&Value>) -> bool {
+    matches!(value.and_then(Value::as_str), Some(s) if s.contains('\0'))
+}
+
+fn array<'a>(input: &'a Value, key: &str) -> impl Iterator<Item = &'a Value> {
+    input
+        .get(key)
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+}
+
+#[cfg(all(test, not(fuzzing)))]
+mod tests {
+    use serde_json::json;
+
+    use super::{find, KnownBug};
+    use crate::{oracle::Verdict, server::ServerVersion};
+
+    fn synapse(version: &str) -> ServerVersion {
+        ServerVersion {
+            name: "Synapse".to_string(),
+            version: version.to_string(),
+        }
+    }
+
+    fn unhandled() -> Verdict {
+        Verdict::ServerError {
+            status: 500,
+            content: r#"{"errcode": "M_UNKNOWN", "error": "Internal server error"}"#.to_string(),
+        }
+    }
+
+    #[test]
+    fn matches_the_trigger_of_a_bug() {
+        let server = synapse("1.66.0");
+        let bug = |input| find("createRoom", Some(&server), &input, &unhandled()).map(|b| b.id);
+
+        assert_eq!(
+            bug(json!({"room_alias_name": "a\0b"})),
+            Some("synapse-13510")
+        );
+        assert_eq!(
+            bug(json!({"invite_3pid": [{"address": "", "id_server": "matrix.org"}]})),
+            Some("synapse-13511")
+        );
+        assert_eq!(
+            bug(json!({"invite_3pid": [{"address": "a@b.c", "id_server": "a b/c"}]})),
+            Some("synapse-13512")
+        );
+        // Any other error of an input with a third party invite is still a finding
+        assert_eq!(
+            bug(json!({"invite_3pid": [{"address": "a@b.c", "id_server": "[::1]:8090"}]})),
+            None
+        );
+        assert_eq!(bug(json!({"name": "a\0b"})), None);
+
+        let not_unhandled = Verdict::ServerError {
+            status: 502,
+            content: "Bad Gateway".to_string(),
+        };
+        let input = json!({"invite_3pid": [{"address": "a@b.c", "id_server": "a b/c"}]});
+        assert!(find("createRoom", Some(&server), &input, &not_unhandled).is_none());
+        assert!(find("login", Some(&server), &input, &unhandled()).is_none());
+    }
+
+    #[test]
+    fn expires_with_the_fix() {
+        let bug = KnownBug {
+            id: "synapse-1",
+            target: "createRoom",
+            server: "Synapse",
+            issue: "https://github.com/matrix-org/synapse/issues/1",
+            fixed_in: Some("1.66.0"),
+            matches: |_, _| true,
+        };
+        assert!(bug.applies_to(None));
+        assert!(bug.applies_to(Some(&synapse("1.65.0"))));
+        assert!(bug.applies_to(Some(&synapse("1.66.0rc2"))));
+        assert!(!bug.applies_to(Some(&synapse("1.66"))));
+        assert!(!bug.applies_to(Some(&synapse("1.67.0 (b=develop,abc123)"))));
+        let dendrite = ServerVersion {
+            name: "Dendrite".to_string(),
+            version: "0.9.0".to_string(),
+        };
+        assert!(!bug.applies_to(Some(&dendrite)));
+    }
+}
@@ -0,0 +1,331 @@
+use serde_json::Value;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use crate::{config::Latency, findings, oracle::Verdict, targets::FuzzTarget};
+
+/// Scale of the median absolute deviation to estimate the standard deviation of normally
+/// distributed samples.
+const MAD_SCALE: f64 = 1.4826;
+
+/// A payload which makes a vulnerable server spend time.
+///
+/// Templates may contain `{seconds}` (the configured delay), `{iterations}` (busy work taking
+/// roughly that long) and `{backtrack}` (input for catastrophic regex backtracking).
+#[derive(Debug)]
+pub struct Payload {
+    pub name: &'static str,
+    pub template: &'static str,
+}
+
+pub const PAYLOADS: &[Payload] = &[
+    Payload {
+        name: "postgres_stacked",
+        template: "'; SELECT pg_sleep({seconds});--",
+    },
+    Payload {
+        name: "postgres_double_quoted",
+        template: "\"; SELECT pg_sleep({seconds});--",
+    },
+    Payload {
+        name: "postgres_concat",
+        template: "'||(SELECT 1 FROM pg_sleep({seconds}))||'",
+    },
+    Payload {
+        name: "postgres_numeric",
+        template: "1 OR 1=(SELECT 1 FROM pg_sleep({seconds}))--",
+    },
+    Payload {
+        name: "sqlite_randomblob",
+        template: "' AND 1=LIKE('ABCDEFG',UPPER(HEX(RANDOMBLOB({iterations}))))--",
+    },
+    Payload {
+        name: "sqlite_numeric",
+        template: "1 AND 1=LIKE('ABCDEFG',UPPER(HEX(RANDOMBLOB({iterations}))))--",
+    },
+    Payload {
+        name: "jinja2_loop",
+        template: "{% for i in range({iterations}) %}{% endfor %}",
+    },
+    Payload {
+        name: "jinja2_expression",
+        template: "{{ range({iterations})|sum }}",
+    },
+    Payload {
+        name: "regex_backtracking",
+        template: "{backtrack}",
+    },
+    Payload {
+        name: "glob_backtracking",
+        template: "*a*a*a*a*a*a*a*a*a*a*{backtrack}",
+    },
+];
+
+impl Payload {
+    pub fn render(&self, seconds: u64) -> String {
+        // Every further character doubles the work of a backtracking regex
+        let backtrack = format!("{}!", "a".repeat(24 + seconds.min(16) as usize));
+        self.template
+            .replace("{seconds}", &seconds.to_string())
+            .replace("{iterations}", &(seconds * 10_000_000).to_string())
+            .replace("{backtrack}", &backtrack)
+    }
+}
+
+/// The recent response times of a target.
+#[derive(Debug, Default)]
+pub struct Baseline {
+    samples: VecDeque<Duration>,
+}
+
+impl Baseline {
+    pub fn record(&mut self, elapsed: Duration, window: usize) {
+        self.samples.push_back(elapsed);
+        while self.samples.len() > window.max(1) {
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn median(&self) -> Option<Duration> {
+        let secs: Vec<f64> = self.samples.iter().map(Duration::as_secs_f64).collect();
+        median(secs).map(Duration::from_secs_f64)
+    }
+
+    /// The response time above which a response is an outlier. `None` while there are fewer
+    /// than [`Latency::min_samples`] samples.
+    ///
+    /// Uses the median and the median absolute deviation, so earlier outliers hardly move the
+    /// limit.
+    pub fn limit(&self, config: &Latency) -> Option<Duration> {
+        if self.samples.len() < config.min_samples.max(1) {
+            return None;
+        }
+        let secs: Vec<f64> = self.samples.iter().map(Duration::as_secs_f64).collect();
+        let center = median(secs.clone())?;
+        let deviation = median(secs.iter().map(|s| (s - center).abs()).collect())? * MAD_SCALE;
+        let excess = (config.threshold * deviation).max(config.min_excess_ms as f64 / 1000.0);
+        Some(Duration::from_secs_f64(center + excess))
+    }
+}
+
+fn median(mut values: Vec<f64>) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    // The two middle values, which are the same one for an odd length
+    let len = values.len();
+    Some((values[(len - 1) / 2] + values[len / 2]) / 2.0)
+}
+
+/// The baselines of all targets in this process.
+#[derive(Default)]
+pub struct Baselines {
+    targets: Mutex<HashMap<&'static str, Baseline>>,
+}
+
+impl Baselines {
+    fn lock(&self) -> MutexGuard<'_, HashMap<&'static str, Baseline>> {
+        self.targets.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn record(&self, target: &'static str, elapsed: Duration, config: &Latency) {
+        self.lock()
+            .entry(target)
+            .or_default()
+            .record(elapsed, config.window);
+    }
+
+    pub fn samples(&self, target: &str) -> usize {
+        self.lock().get(target).map_or(0, Baseline::len)
+    }
+
+    /// The outlier limit and the median of a target, see [`Baseline::limit`].
+    pub fn limit(&self, target: &str, config: &Latency) -> Option<(Duration, Duration)> {
+        let targets = self.lock();
+        let baseline = targets.get(target)?;
+        Some((baseline.limit(config)?, baseline.median()?))
+    }
+}
+
+/// Compares the response time of a request with the target's baseline and returns
+/// [`Verdict::SlowResponse`] if it is an outlier.
+///
+/// An outlier is resent [`Latency::confirmations`] times and only reported if every resend is
+/// an outlier too, as a single slow response is usually the server being busy. Responses which
+/// are findings anyway are neither checked nor part of the baseline.
+#[no_coverage]
+pub fn check<T: FuzzTarget>(
+    path: &str,
+    body: &Value,
+    verdict: Verdict,
+    elapsed: Duration,
+) -> Verdict {
+    if verdict.is_finding() {
+        return verdict;
+    }
+    let config = match crate::config() {
+        Ok(v) => &v.latency,
+        Err(_) => return verdict,
+    };
+    let baselines = crate::baselines();
+    let (limit, median) = match baselines.limit(T::NAME, config) {
+        Some((limit, median)) if elapsed > limit => (limit, median),
+        _ => {
+            baselines.record(T::NAME, elapsed, config);
+            return verdict;
+        }
+    };
+
+    let mut fastest = elapsed;
+    for _ in 0..config.confirmations {
+        let (confirmation, elapsed) = T::exchange(path, body);
+        if confirmation.is_finding() {
+            return confirmation;
+        }
+        if elapsed <= limit {
+            baselines.record(T::NAME, elapsed, config);
+            return verdict;
+        }
+        fastest = fastest.min(elapsed);
+    }
+    Verdict::SlowResponse {
+        elapsed_ms: fastest.as_millis() as u64,
+        baseline_ms: median.as_millis() as u64,
+    }
+}
+
+/// A payload injected into a request which caused a finding.
+#[derive(Debug)]
+pub struct Injection {
+    /// JSON pointer of the string the payload was appended to.
+    pub pointer: String,
+    pub payload: &'static str,
+    pub request: findings::Request,
+    pub verdict: Verdict,
+}
+
+/// Appends every payload of [`PAYLOADS`] to every string in `body`, one at a time, and returns
+/// the injections which caused a finding, usually [`Verdict::SlowResponse`].
+///
+/// The target's baseline is first filled up by sending `body` unchanged.
+#[no_coverage]
+pub fn probe<T: FuzzTarget>(path: &str, body: &Value) -> Vec<Injection> {
+    let config = match crate::config() {
+        Ok(v) => &v.latency,
+        Err(_) => return Vec::new(),
+    };
+    let baselines = crate::baselines();
+    for _ in baselines.samples(T::NAME)..config.min_samples {
+        let (_, elapsed) = T::exchange(path, body);
+        baselines.record(T::NAME, elapsed, config);
+    }
+
+    let mut found = Vec::new();
+    for (pointer, payload, injected) in injections(body, config.sleep_secs) {
+        let (verdict, elapsed) = T::exchange(path, &injected);
+        let verdict = check::<T>(path, &injected, verdict, elapsed);
+        if verdict.is_finding() {
+            found.push(Injection {
+                pointer,
+                payload: payload.name,
+                request: findings::Request {
+                    target: T::NAME.to_string(),
+                    method: T::METHOD.to_string(),
+                    path: path.to_string(),
+                    body: injected,
+                },
+                verdict,
+            });
+        }
+    }
+    found
+}
+
+/// Copies of `body` with a payload appended to one of its strings.
+pub fn injections(body: &Value, seconds: u64) -> Vec<(String, &'static Payload, Value)> {
+    let mut injections = Vec::new();
+    for pointer in string_pointers(body) {
+        for payload in PAYLOADS {
+            let mut injected = body.clone();
+            if let Some(Value::String(s)) = injected.pointer_mut(&pointer) {
+                s.push_str(&payload.render(seconds));
+            }
+            injections.push((pointer.clone(), payload, injected));
+        }
+    }
+    injections
+}
+
+/// JSON pointers to the strings in `value`.
+fn string_pointers(value: &Value) -> Vec<String> {
+    let mut pointers = Vec::new();
+    collect_strings(value, String::new(), &mut pointers);
+    pointers
+}
+
+fn collect_strings(value: &Value, pointer: String, pointers: &mut Vec<String>) {
+    match value {
+        Value::String(_) => pointers.push(pointer),
+        Value::Array(values) => {
+            for (i, value) in values.iter().enumerate() {
+                collect_strings(value, format!("{}/{}", pointer, i), pointers);
+            }
+        }
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                let key = key.replace('~', "~0").replace('/', "~1");
+                collect_strings(value, format!("{}/{}", pointer, key), pointers);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(all(test, not(fuzzing)))]
+mod tests {
+    use serde_json::json;
+    use std::time::Duration;
+
+    use super::{injections, Baseline, PAYLOADS};
+    use crate::config::Latency;
+
+    #[test]
+    fn flags_outliers_and_injects_strings() {
+        let config = Latency {
+            min_samples: 5,
+            min_excess_ms: 100,
+            ..Latency::default()
+        };
+        let mut baseline = Baseline::default();
+        for ms in [50, 52, 48, 51] {
+            baseline.record(Duration::from_millis(ms), config.window);
+        }
+        assert_eq!(baseline.limit(&config), None);
+        baseline.record(Duration::from_millis(2000), config.window);
+        // The earlier outlier barely moves the limit
+        let limit = baseline.limit(&config).unwrap();
+        assert!(limit > Duration::from_millis(150) && limit < Duration::from_millis(200));
+
+        let body = json!({"name": "a", "initial_state": [{"a/b": "c", "n": 1}]});
+        let injected = injections(&body, 5);
+        assert_eq!(injected.len(), 2 * PAYLOADS.len());
+        assert_eq!(injected[0].0, "/initial_state/0/a~1b");
+        assert_eq!(
+            injected[0].2["initial_state"][0]["a/b"],
+            json!("c'; SELECT pg_sleep(5);--")
+        );
+        assert_eq!(injected[0].2["name"], json!("a"));
+    }
+}
@@ -3,93 +3,186 @@
 #![allow(dead_code)]
 #![allow(clippy::too_many_arguments)]
 
-use crate::types::{Flow, LoginGet, LoginPost};
+#[cfg(all(feature = "password_auth", feature = "token_auth"))]
+compile_error!(
+    "The `password_auth` and `token_auth` features are mutually exclusive. Build with \
+     `--no-default-features --features fuzzing,token_auth` to log in with a shared secret."
+);
+#[cfg(not(any(feature = "password_auth", feature = "token_auth")))]
+compile_error!("Enable either the `password_auth` or the `token_auth` feature.");
+
+use crate::{
+    allowlist::Allowlist, config::FuzzConfig, error::FuzzError, identities::Pool,
+    latency::Baselines, metrics::Scraper, server::ServerVersion, session::Session,
+};
 use once_cell::sync::OnceCell;
-use std::{collections::HashMap, env};
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
 
+pub mod allowlist;
+pub mod authz;
+pub mod config;
+pub mod corpus;
+pub mod error;
+pub mod feedback;
+pub mod findings;
+pub mod identities;
+pub mod known_bugs;
+pub mod latency;
+pub mod metrics;
+pub mod minimize;
+pub mod oracle;
+pub mod postconditions;
+pub mod provision;
+pub mod replay;
+pub mod scenario;
+pub mod server;
+pub mod server_coverage;
+pub mod server_log;
+pub mod session;
+pub mod targets;
 pub mod types;
 
+/// User agent of all requests, which identifies the fuzzer's requests in the server log.
+pub const USER_AGENT: &str = "synapse-fuzzer";
+
+#[no_coverage]
+pub fn config() -> Result<&'static FuzzConfig, FuzzError> {
+    static INSTANCE: OnceCell<FuzzConfig> = OnceCell::new();
+    INSTANCE.get_or_try_init(FuzzConfig::load)
+}
+
+#[no_coverage]
+pub fn allowlist() -> Result<&'static Allowlist, FuzzError> {
+    static INSTANCE: OnceCell<Allowlist> = OnceCell::new();
+    INSTANCE.get_or_try_init(|| Allowlist::load(&config()?.allowlist_file))
+}
+
+/// The homeserver implementation and version, or `None` if the server does not report it.
+#[no_coverage]
+pub fn server_version() -> &'static Option<ServerVersion> {
+    static INSTANCE: OnceCell<Option<ServerVersion>> = OnceCell::new();
+    INSTANCE.get_or_init(ServerVersion::fetch)
+}
+
+#[no_coverage]
+pub fn session() -> &'static Session {
+    static INSTANCE: OnceCell<Session> = OnceCell::new();
+    INSTANCE.get_or_init(Session::default)
+}
+
+#[no_coverage]
+pub fn identities() -> &'static Pool {
+    static INSTANCE: OnceCell<Pool> = OnceCell::new();
+    INSTANCE.get_or_init(Pool::default)
+}
+
+/// Response times of the targets, see [`latency`].
+#[no_coverage]
+pub fn baselines() -> &'static Baselines {
+    static INSTANCE: OnceCell<Baselines> = OnceCell::new();
+    INSTANCE.get_or_init(Baselines::default)
+}
+
+/// The last scrape of the homeserver's metrics, see [`metrics`].
+#[no_coverage]
+pub fn metrics() -> &'static Scraper {
+    static INSTANCE: OnceCell<Scraper> = OnceCell::new();
+    INSTANCE.get_or_init(Scraper::default)
+}
+
+/// The access token of the current session, logging in first if needed.
 #[no_coverage]
-pub fn access_token() -> &'static String {
-    static INSTANCE: OnceCell<String> = OnceCell::new();
-    INSTANCE.get_or_init(login)
+pub fn access_token() -> Result<String, FuzzError> {
+    session().access_token()
 }
 
 #[no_coverage]
-pub fn client() -> &'static reqwest::blocking::Client {
+pub fn client() -> Result<&'static reqwest::blocking::Client, FuzzError> {
     static INSTANCE: OnceCell<reqwest::blocking::Client> = OnceCell::new();
-    INSTANCE.get_or_init(|| {
+    INSTANCE.get_or_try_init(|| {
+        let config = config()?;
         reqwest::blocking::Client::builder()
-            .connect_timeout(Some(std::time::Duration::from_secs(30)))
-            .user_agent("synapse-fuzzer")
+            .connect_timeout(Some(config.connect_timeout()))
+            .timeout(Some(config.request_timeout()))
+            .user_agent(USER_AGENT)
             .gzip(true)
             .build()
-            .unwrap()
+            .map_err(FuzzError::Client)
     })
 }
 
+/// Whether the server answers a cheap request within the liveness timeout.
 #[no_coverage]
-fn login() -> String {
-    let server = match env::var("MATRIX_SERVER") {
-        Ok(v) => v,
-        Err(_) => "http://localhost:8008".to_string(),
+pub fn server_reachable() -> bool {
+    let (client, config) = match (client(), config()) {
+        (Ok(client), Ok(config)) => (client, config),
+        _ => return false,
     };
-    let username = match env::var("MATRIX_USERNAME") {
-        Ok(v) => v,
-        Err(e) => panic!("$MATRIX_USERNAME is not set ({})", e),
-    };
-    let password = match env::var("MATRIX_PASSWORD") {
-        Ok(v) => v,
-        Err(e) => panic!("$MATRIX_PASSWORD is not set ({})", e),
-    };
-    let client = crate::client();
-    let res: LoginGet = client
-        .get(format!("{}/_matrix/client/v3/login", server))
-        .send()
-        .unwrap()
-        .json()
-        .unwrap();
-    assert!(res.flows.contains(&Flow {
-        type_: "m.login.password".to_string(),
-    }));
-
-    let mut map = HashMap::new();
-    map.insert("type", "m.login.password");
-    map.insert("user", &username);
-    map.insert("password", &password);
-    let res: LoginPost = client
-        .post(format!("{}/_matrix/client/v3/login", server))
-        .json(&map)
-        .send()
-        .unwrap()
-        .json()
-        .unwrap();
+    let request = client
+        .get(config.url("/_matrix/key/v2/server"))
+        .timeout(config.liveness_timeout());
+    match request.send() {
+        Ok(resp) => resp.status().is_success(),
+        Err(_) => false,
+    }
+}
 
-    res.access_token
+/// Sends one of the fuzzer's own requests, retrying on transient failures.
+#[no_coverage]
+pub(crate) fn request_json<T: DeserializeOwned>(
+    method: Method,
+    url: &str,
+    body: Option<&Value>,
+) -> Result<T, FuzzError> {
+    let config = config()?;
+    error::retry(config.timeouts.retries, config.retry_backoff(), || {
+        let mut request = client()?.request(method.clone(), url);
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+        let network = |source| FuzzError::Network {
+            url: url.to_string(),
+            source,
+        };
+        let resp = request.send().map_err(network)?;
+        let status = resp.status();
+        let content = resp.text().map_err(network)?;
+        if !status.is_success() {
+            return Err(FuzzError::Status {
+                url: url.to_string(),
+                status: status.as_u16(),
+                body: content,
+            });
+        }
+        serde_json::from_str(&content).map_err(|source| FuzzError::Decode {
+            url: url.to_string(),
+            source,
+        })
+    })
 }
 
 #[cfg(all(test, not(fuzzing)))]
 mod tests {
-    use std::{env, time::Instant};
+    use std::{
+        path::Path,
+        time::{Duration, Instant},
+    };
 
-    use reqwest::header::{HeaderValue, CONTENT_TYPE};
     use serde_json::json;
 
-    use crate::types::create_room::CreateRoomMagicJSON;
+    use crate::{
+        oracle::Verdict,
+        replay::Outcome,
+        targets::{execute_body, send, CreateRoom, FuzzTarget},
+        types::create_room::{CreateRoomMagicJSON, StateEventJSON},
+    };
 
     #[test]
     #[no_coverage]
     fn connection_test() {
-        let server = match env::var("MATRIX_SERVER") {
-            Ok(v) => v,
-            Err(_) => "http://localhost:8008".to_string(),
-        };
-        let client = crate::client();
-        let resp = client
-            .get(format!("{}/_matrix/key/v2/server", server))
-            .send()
-            .unwrap();
-        assert!(resp.status().is_success());
+        assert!(crate::server_reachable());
     }
 
     #[test]
@@ -103,23 +196,36 @@ mod tests {
             topic: Some("a".to_string()),
             ..Default::default()
         };
-        let access_token = crate::access_token();
-        let client = crate::client();
-        let server = match env::var("MATRIX_SERVER") {
-            Ok(v) => v,
-            Err(_) => "http://localhost:8008".to_string(),
-        };
-        let resp = client
-            .post(format!("{}/_matrix/client/v3/createRoom", server))
-            .header("Authorization", format!("Bearer {}", access_token))
-            .json(&content)
-            .send()
-            .unwrap();
+        let body = serde_json::to_value(&content).unwrap();
+        let resp = send::<CreateRoom>(CreateRoom::PATH, &body).unwrap();
 
         assert!(!resp.status().is_success());
         assert!(resp.text().unwrap().contains("Internal server error"));
     }
 
+    #[test]
+    #[no_coverage]
+    fn null_in_room_alias_is_known() {
+        let content = CreateRoomMagicJSON {
+            room_alias_name: Some("\0".to_string()),
+            ..Default::default()
+        };
+        let body = serde_json::to_value(&content).unwrap();
+        let verdict = execute_body::<CreateRoom>(CreateRoom::PATH, &body);
+
+        assert!(
+            matches!(
+                verdict,
+                Verdict::KnownBug {
+                    bug: "synapse-13510",
+                    ..
+                }
+            ),
+            "{}",
+            verdict
+        );
+    }
+
     #[test]
     #[no_coverage]
     fn sql_injection_test() {
@@ -197,19 +303,13 @@ mod tests {
             ],
             ..Default::default()
         };
-        let access_token = crate::access_token();
-        let client = crate::client();
+        let body = serde_json::to_value(&content).unwrap();
         let start = Instant::now();
-        let server = match env::var("MATRIX_SERVER") {
-            Ok(v) => v,
-            Err(_) => "http://localhost:8008".to_string(),
+        // The deadline of the target is shorter than the sleep
+        let resp = match send::<CreateRoom>(CreateRoom::PATH, &body) {
+            Err(e) if crate::targets::is_timeout(&e) => panic!("pg_sleep was executed"),
+            res => res.unwrap(),
         };
-        let resp = client
-            .post(format!("{}/_matrix/client/v3/createRoom", server))
-            .header("Authorization", format!("Bearer {}", access_token))
-            .json(&content)
-            .send()
-            .unwrap();
         let duration = start.elapsed();
         println!("Time elapsed in request is: {:?}", duration);
         println!("{:?}", resp);
@@ -219,228 +319,53 @@ mod tests {
 
         assert!(!status.is_success());
         assert!(text.contains("Internal server error"));
+        assert!(duration < Duration::from_secs(50), "pg_sleep was executed");
     }
 
     #[test]
     #[no_coverage]
-    fn weird_req() {
-        let content = std::fs::read_to_string("./weird_ones/af84a60a1b7997b4.json").unwrap();
-        let access_token = crate::access_token();
-        let client = crate::client();
-        let server = match env::var("MATRIX_SERVER") {
-            Ok(v) => v,
-            Err(_) => "http://localhost:8008".to_string(),
-        };
-        let resp = client
-            .post(format!("{}/_matrix/client/v3/createRoom", server))
-            .header("Authorization", format!("Bearer {}", access_token))
-            .header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
-            .body(content)
-            .send();
-        assert!(resp.is_err())
-    }
-}
-
-#[cfg(all(fuzzing, test))]
-mod tests {
-    use crate::types::{create_room::CreateRoomMagicJSON, LoginPostReq};
-    use std::env;
-
-    fn login(data: &LoginPostReq) -> bool {
-        let mut json_data = data.clone();
-        // We hardcode the type for better fuzzing
-        cfg_if::cfg_if! {
-            if #[cfg(feature = "token_auth")] {
-                json_data._type = "com.devture.shared_secret_auth".to_string();
-            } else {
-                json_data._type = "m.login.password".to_string();
-            }
-        }
-
-        let username = match env::var("MATRIX_USERNAME") {
-            Ok(v) => v,
-            Err(e) => panic!("$MATRIX_USERNAME is not set ({})", e),
+    fn blind_injection_test() {
+        let content = CreateRoomMagicJSON {
+            name: Some("name".to_string()),
+            topic: Some("topic".to_string()),
+            initial_state: vec![StateEventJSON {
+                _type: "org.matrix.fuzz".to_string(),
+                state_key: "state_key".to_string(),
+                content: json!({}),
+            }],
+            ..Default::default()
         };
-
-        if json_data.user.is_some() {
-            json_data.user = Some(username.clone());
-        }
-        if let Some(identifier) = &mut json_data.identifier {
-            identifier.user = username;
-            identifier._type = "m.id.user".to_string();
+        let body = serde_json::to_value(&content).unwrap();
+        let injections = crate::latency::probe::<CreateRoom>(CreateRoom::PATH, &body);
+        for injection in &injections {
+            println!(
+                "{} at {}: {}",
+                injection.payload, injection.pointer, injection.verdict
+            );
         }
 
-        if let Some(user) = &json_data.user {
-            if user.contains('\0') {
-                json_data.user = Some(user.replace('\0', ""));
-            }
-        }
-        if let Some(medium) = &json_data.medium {
-            if medium.contains('\0') {
-                json_data.medium = Some(medium.replace('\0', ""));
-            }
-        }
-        if let Some(address) = &json_data.address {
-            if address.contains('\0') {
-                json_data.address = Some(address.replace('\0', ""));
-            }
-        }
-        if let Some(user) = &json_data.user {
-            if user.contains('\0') {
-                json_data.user = Some(user.replace('\0', ""));
-            }
-        }
-        /*if let Some(password) = &json_data.password {
-            if password.contains('\0' {
-                json_data.password = Some(password.replace('\0', ""));
-            }
-        }*/
-
-        let client = crate::client();
-        let server = match env::var("MATRIX_SERVER") {
-            Ok(v) => v,
-            Err(_) => "http://localhost:8008".to_string(),
-        };
-        let resp = client
-            .post(format!("{}/_matrix/client/v3/login", server))
-            .json(&json_data)
-            .send();
-        if let Ok(resp) = resp {
-            let status = resp.status();
-            if !status.is_success() {
-                /*if status == 400 {
-                    return true;
-                }*/
-                let content = resp.text();
-                if let Ok(ref content) = content {
-                    if content.contains("Unknown login type")
-                        || content.contains("Invalid login submission")
-                        || content.contains("Invalid username or password")
-                    {
-                        return true;
-                    }
-                }
-                println!("Status: {:?}", status);
-                println!("Content: {:?}", content);
-            }
-        }
-        false
+        // A hang or any other finding is as suspicious as a slow response
+        assert!(injections.is_empty());
     }
 
     #[test]
-    fn fuzz_login() {
-        let client = crate::client();
-        let server = match env::var("MATRIX_SERVER") {
-            Ok(v) => v,
-            Err(_) => "http://localhost:8008".to_string(),
-        };
-        let resp = client
-            .get(format!("{}/_matrix/key/v2/server", server))
-            .send()
-            .unwrap();
-        if !resp.status().is_success() {
-            panic!("Failed to connect");
-        }
-
-        let result = fuzzcheck::fuzz_test(login)
-            .default_options()
-            .stop_after_first_test_failure(true)
-            .launch();
-        assert!(!result.found_test_failure);
-    }
-
-    fn create_room(data: &CreateRoomMagicJSON) -> bool {
-        let mut json_data = data.clone();
-        for mut state in &mut json_data.initial_state {
-            if state.content.is_array()
-                || state.content.is_boolean()
-                || state.content.is_null()
-                || state.content.is_string()
-                || state.content.is_u64()
-            {
-                state.content = serde_json::Value::Object(serde_json::Map::new());
-            }
-        }
-
-        // HACK due to https://github.com/matrix-org/synapse/issues/13510
-        /*if let Some(room_alias_name) = &json_data.room_alias_name {
-            if room_alias_name.contains('\0') {
-                json_data.room_alias_name = Some(room_alias_name.replace('\0', ""));
-            }
-        }*/
-        // HACK due to NUL in type or state_key
-        for state in json_data.initial_state.iter_mut() {
-            state._type = state._type.replace('\0', "");
-            state.state_key = state.state_key.replace('\0', "");
-        }
-
-        /*// HACK due to https://github.com/matrix-org/synapse/issues/13511
-        if let Some(pids) = &data.invite_3pid {
-            for pid in pids {
-                if pid.address.is_empty() {
-                    return true;
-                }
-            }
-        }*/
-
-        // TODO: Login once and reuse the access token
-        let access_token = crate::access_token();
-        let client = crate::client();
-        let server = match env::var("MATRIX_SERVER") {
-            Ok(v) => v,
-            Err(_) => "http://localhost:8008".to_string(),
-        };
-        let resp = client
-            .post(format!("{}/_matrix/client/v3/createRoom", server))
-            .header("Authorization", format!("Bearer {}", access_token))
-            .json(&json_data)
-            .send();
-        if let Ok(resp) = resp {
-            let status = resp.status();
-            if !status.is_success() {
-                //println!("Status: {:?}", status);
-                let content = resp.text();
-                if let Ok(ref content) = content {
-                    if content.contains("M_ROOM_IN_USE")
-                        || content.contains("Invalid characters in room alias")
-                        || content.contains("':' is not permitted in the room alias name. Please note this expects a local part — 'wombat', not '#wombat:example.com'.")
-                        || content.contains("M_UNSUPPORTED_ROOM_VERSION")
-                        || content.contains("Invalid user_id")
-                        || content.contains("is not a valid preset")
-                        || content.contains("You are not allowed to set others state")
-                        || content.contains("JSON integer out of range")
-                        || content.contains(" too large")
-                    {
-                        return true;
-                    }
-                }
-                println!("Content: {:?}", content);
-
-                return false;
-            }
+    #[no_coverage]
+    fn weird_req() {
+        let target = crate::targets::find(CreateRoom::NAME).unwrap();
+        let results = crate::replay::replay_dir(&target, Path::new("./weird_ones"), true).unwrap();
+        assert!(!results.is_empty());
+        for result in results {
+            println!("{}: {}", result.path.display(), result.outcome);
+            assert!(!matches!(result.outcome, Outcome::Invalid(_)));
         }
-        true
     }
+}
 
-    #[test]
-    fn fuzz_create_room() {
-        let client = crate::client();
-        let server = match env::var("MATRIX_SERVER") {
-            Ok(v) => v,
-            Err(_) => "http://localhost:8008".to_string(),
-        };
-        let resp = client
-            .get(format!("{}/_matrix/key/v2/server", server))
-            .send()
-            .unwrap();
-        if !resp.status().is_success() {
-            panic!("Failed to connect");
-        }
+#[cfg(all(fuzzing, test))]
+mod tests {
+    use crate::targets::{CreateRoom, Login, Scenario};
 
-        let result = fuzzcheck::fuzz_test(create_room)
-            .default_options()
-            .stop_after_first_test_failure(true)
-            .launch();
-        assert!(!result.found_test_failure);
-    }
+    crate::fuzzcheck_target!(fuzz_login, Login);
+    crate::fuzzcheck_target!(fuzz_create_room, CreateRoom);
+    crate::fuzzcheck_target!(fuzz_scenario, Scenario);
 }
@@ -0,0 +1,224 @@
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::{Mutex, PoisonError},
+};
+
+use crate::{
+    config::GrowthLimit,
+    corpus::stable_hash,
+    error::FuzzError,
+    feedback::{self, Channel},
+    oracle::Verdict,
+    targets::FuzzTarget,
+};
+
+/// Values of the series of a scrape, keyed by name and labels, e.g.
+/// `synapse_util_caches_cache_size{name="room_version"}`.
+pub type Snapshot = HashMap<String, f64>;
+
+/// Parses the Prometheus text format. Comments and unparseable lines are skipped.
+pub fn parse(text: &str) -> Snapshot {
+    let mut snapshot = Snapshot::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // Label values may contain spaces, the value follows the labels
+        let split = match line.rfind('}') {
+            Some(end) => Some(line.split_at(end + 1)),
+            None => line.split_once(' '),
+        };
+        let (series, rest) = match split {
+            Some(v) => v,
+            None => continue,
+        };
+        let value = rest.split_whitespace().next().map(str::parse::<f64>);
+        if let Some(Ok(value)) = value {
+            snapshot.insert(series.trim().to_string(), value);
+        }
+    }
+    snapshot
+}
+
+/// Series which changed between two scrapes and by how much. A series missing before counts
+/// as 0.
+pub fn deltas(before: &Snapshot, after: &Snapshot) -> Vec<(String, f64)> {
+    let mut deltas: Vec<(String, f64)> = after
+        .iter()
+        .map(|(series, value)| (series, value - before.get(series).copied().unwrap_or(0.0)))
+        .filter(|(_, delta)| delta.is_finite() && *delta != 0.0)
+        .map(|(series, delta)| (series.clone(), delta))
+        .collect();
+    deltas.sort_by(|a, b| a.0.cmp(&b.0));
+    deltas
+}
+
+/// Feature of a change for fuzzcheck. Changes of the same series only differ in their sign and
+/// order of magnitude, so noise does not make every input look new.
+pub fn feature(series: &str, delta: f64) -> u64 {
+    let magnitude = delta.abs().log2().floor().max(0.0) as i64 + 1;
+    let magnitude = if delta < 0.0 { -magnitude } else { magnitude };
+    stable_hash(format!("{}/{}", series, magnitude).as_bytes())
+}
+
+/// The first series which grew by more than its limit.
+pub fn exceeded<'a>(
+    limits: &'a [GrowthLimit],
+    deltas: &[(String, f64)],
+) -> Option<(String, f64, &'a GrowthLimit)> {
+    deltas.iter().find_map(|(series, delta)| {
+        let limit = limits
+            .iter()
+            .find(|l| series.starts_with(&l.prefix) && *delta > l.max_delta)?;
+        Some((series.clone(), *delta, limit))
+    })
+}
+
+/// Scrapes the homeserver's metrics endpoint and remembers the last scrape.
+#[derive(Default)]
+pub struct Scraper {
+    last: Mutex<Option<Snapshot>>,
+}
+
+impl Scraper {
+    /// Changes since the last scrape. `None` on the first one.
+    #[no_coverage]
+    pub fn changes(&self, url: &str) -> Result<Option<Vec<(String, f64)>>, FuzzError> {
+        let snapshot = scrape(url)?;
+        let mut last = self.last.lock().unwrap_or_else(PoisonError::into_inner);
+        let changes = last.as_ref().map(|before| deltas(before, &snapshot));
+        *last = Some(snapshot);
+        Ok(changes)
+    }
+}
+
+#[no_coverage]
+fn scrape(url: &str) -> Result<Snapshot, FuzzError> {
+    let network = |source| FuzzError::Network {
+        url: url.to_string(),
+        source,
+    };
+    let resp = crate::client()?.get(url).send().map_err(network)?;
+    let status = resp.status();
+    let text = resp.text().map_err(network)?;
+    if !status.is_success() {
+        return Err(FuzzError::Status {
+            url: url.to_string(),
+            status: status.as_u16(),
+            body: text,
+        });
+    }
+    Ok(parse(&text))
+}
+
+/// Scrapes the metrics after a request, feeds the changed series to fuzzcheck and checks the
+/// growth limits.
+///
+/// An input exceeding a limit is resent [`crate::config::Metrics::confirmations`] times and only
+/// reported as [`Verdict::ResourceGrowth`] if the series exceeds the limit every time, as other
+/// requests and background jobs also move the metrics.
+#[no_coverage]
+pub fn check<T: FuzzTarget>(path: &str, body: &Value, verdict: Verdict) -> Verdict {
+    let config = match crate::config() {
+        Ok(v) => &v.metrics,
+        Err(_) => return verdict,
+    };
+    let url = match &config.url {
+        Some(v) => v,
+        None => return verdict,
+    };
+    let changes = match crate::metrics().changes(url) {
+        Ok(Some(v)) => v,
+        Ok(None) => return verdict,
+        Err(e) => {
+            println!("Unable to scrape the metrics ({})", e);
+            return verdict;
+        }
+    };
+    for (series, delta) in &changes {
+        if config.feedback.iter().any(|p| series.starts_with(p)) {
+            feedback::observe(Channel::Metrics, feature(series, *delta));
+        }
+    }
+    if verdict.is_finding() {
+        return verdict;
+    }
+
+    let (series, mut smallest, limit) = match exceeded(&config.growth, &changes) {
+        Some(v) => v,
+        None => return verdict,
+    };
+    for _ in 0..config.confirmations {
+        let (confirmation, _) = T::exchange(path, body);
+        if confirmation.is_finding() {
+            return confirmation;
+        }
+        let delta = match crate::metrics().changes(url) {
+            Ok(Some(changes)) => changes
+                .iter()
+                .find(|(s, _)| s == &series)
+                .map_or(0.0, |(_, d)| *d),
+            _ => return verdict,
+        };
+        if delta <= limit.max_delta {
+            return verdict;
+        }
+        smallest = smallest.min(delta);
+    }
+    Verdict::ResourceGrowth {
+        series,
+        growth: smallest,
+    }
+}
+
+#[cfg(all(test, not(fuzzing)))]
+mod tests {
+    use super::{deltas, exceeded, feature, parse};
+    use crate::config::GrowthLimit;
+
+    #[test]
+    fn parses_and_compares_scrapes() {
+        let before = parse(
+            "# HELP process_resident_memory_bytes Resident memory size in bytes.\n\
+             # TYPE process_resident_memory_bytes gauge\n\
+             process_resident_memory_bytes 1.0e+08\n\
+             synapse_util_caches_cache_size{name=\"room version\"} 3.0\n",
+        );
+        let after = parse(
+            "process_resident_memory_bytes 1.5e+08\n\
+             synapse_util_caches_cache_size{name=\"room version\"} 3.0\n\
+             synapse_storage_events_persisted_events_total 7.0 1650000000000\n",
+        );
+        assert_eq!(
+            before.get("synapse_util_caches_cache_size{name=\"room version\"}"),
+            Some(&3.0)
+        );
+        let changes = deltas(&before, &after);
+        assert_eq!(
+            changes,
+            vec![
+                ("process_resident_memory_bytes".to_string(), 5.0e7),
+                (
+                    "synapse_storage_events_persisted_events_total".to_string(),
+                    7.0
+                ),
+            ]
+        );
+
+        assert_eq!(feature("a", 5.0), feature("a", 7.0));
+        assert_ne!(feature("a", 5.0), feature("a", 8.0));
+        assert_ne!(feature("a", 5.0), feature("a", -5.0));
+
+        let limits = vec![GrowthLimit {
+            prefix: "process_resident_memory_bytes".to_string(),
+            max_delta: 1.6e7,
+        }];
+        let (series, growth, _) = exceeded(&limits, &changes).unwrap();
+        assert_eq!(
+            (series.as_str(), growth),
+            ("process_resident_memory_bytes", 5.0e7)
+        );
+    }
+}
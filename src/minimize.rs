@@ -0,0 +1,231 @@
+use serde_json::{Map, Value};
+
+/// Upper bound of candidates checked by [`minimize`]. Every check is a request to the server.
+pub const DEFAULT_MAX_CHECKS: usize = 2000;
+
+/// Shrinks a JSON input while `still_fails` keeps returning `true`.
+///
+/// `still_fails` is called with every candidate and should re-send it to the server, so the
+/// result is checked against the live oracle. Works for any target as inputs are minimised as
+/// plain JSON: array elements and object fields are removed with delta debugging, strings are
+/// shortened and subtrees are replaced by simpler values until no step makes progress anymore.
+pub fn minimize(value: Value, still_fails: impl FnMut(&Value) -> bool) -> Value {
+    minimize_with_budget(value, DEFAULT_MAX_CHECKS, still_fails)
+}
+
+pub fn minimize_with_budget(
+    value: Value,
+    max_checks: usize,
+    still_fails: impl FnMut(&Value) -> bool,
+) -> Value {
+    let mut minimizer = Minimizer {
+        value,
+        still_fails,
+        checks: 0,
+        max_checks,
+    };
+    loop {
+        let before = minimizer.value.clone();
+        minimizer.minimize_at(String::new());
+        if minimizer.value == before || minimizer.exhausted() {
+            break;
+        }
+    }
+    minimizer.value
+}
+
+struct Minimizer<F> {
+    value: Value,
+    still_fails: F,
+    checks: usize,
+    max_checks: usize,
+}
+
+impl<F: FnMut(&Value) -> bool> Minimizer<F> {
+    fn exhausted(&self) -> bool {
+        self.checks >= self.max_checks
+    }
+
+    fn node(&self, pointer: &str) -> Option<&Value> {
+        self.value.pointer(pointer)
+    }
+
+    /// Replaces the node at `pointer` with `replacement` if the result still fails.
+    fn try_replace(&mut self, pointer: &str, replacement: Value) -> bool {
+        if self.exhausted() || self.node(pointer) == Some(&replacement) {
+            return false;
+        }
+        let mut candidate = self.value.clone();
+        match candidate.pointer_mut(pointer) {
+            Some(node) => *node = replacement,
+            None => return false,
+        }
+        self.checks += 1;
+        if (self.still_fails)(&candidate) {
+            self.value = candidate;
+            return true;
+        }
+        false
+    }
+
+    fn minimize_at(&mut self, pointer: String) {
+        let node = match self.node(&pointer) {
+            Some(v) => v.clone(),
+            None => return,
+        };
+        // Replacing the whole subtree is the biggest possible step, try it first
+        for simpler in simpler_values(&node) {
+            if self.try_replace(&pointer, simpler) {
+                break;
+            }
+        }
+
+        match self.node(&pointer).cloned() {
+            Some(Value::Array(_)) => {
+                self.remove_chunks(
+                    &pointer,
+                    |v| v.as_array().map_or(0, Vec::len),
+                    remove_elements,
+                );
+                let len = self
+                    .node(&pointer)
+                    .and_then(Value::as_array)
+                    .map_or(0, Vec::len);
+                for i in 0..len {
+                    self.minimize_at(format!("{}/{}", pointer, i));
+                }
+            }
+            Some(Value::Object(_)) => {
+                self.remove_chunks(
+                    &pointer,
+                    |v| v.as_object().map_or(0, Map::len),
+                    remove_fields,
+                );
+                let keys: Vec<String> = self
+                    .node(&pointer)
+                    .and_then(Value::as_object)
+                    .map(|o| o.keys().cloned().collect())
+                    .unwrap_or_default();
+                for key in keys {
+                    self.minimize_at(format!("{}/{}", pointer, escape(&key)));
+                }
+            }
+            Some(Value::String(_)) => {
+                self.remove_chunks(
+                    &pointer,
+                    |v| v.as_str().map_or(0, |s| s.chars().count()),
+                    remove_chars,
+                );
+            }
+            _ => {}
+        }
+    }
+
+    /// Delta debugging over the children of the node at `pointer`: removes chunks of children,
+    /// halving the chunk size until single children have been tried.
+    fn remove_chunks(
+        &mut self,
+        pointer: &str,
+        len: fn(&Value) -> usize,
+        remove: fn(&Value, usize, usize) -> Value,
+    ) {
+        let mut chunk = self.node(pointer).map_or(0, len) / 2;
+        chunk = chunk.max(1);
+        loop {
+            let mut start = 0;
+            while let Some(node) = self.node(pointer) {
+                if start >= len(node) || self.exhausted() {
+                    break;
+                }
+                let candidate = remove(node, start, chunk);
+                // On success the following children moved to `start`
+                if !self.try_replace(pointer, candidate) {
+                    start += chunk;
+                }
+            }
+            if chunk == 1 || self.exhausted() {
+                break;
+            }
+            chunk /= 2;
+        }
+    }
+}
+
+/// Candidates for replacing a node, simplest first.
+fn simpler_values(value: &Value) -> Vec<Value> {
+    match value {
+        Value::Null => vec![],
+        Value::Bool(_) => vec![Value::Null, Value::Bool(false)],
+        Value::Number(_) => vec![Value::Null, Value::from(0)],
+        Value::String(_) => vec![Value::Null, Value::from("")],
+        Value::Array(_) => vec![Value::Null, Value::Array(Vec::new())],
+        Value::Object(_) => vec![Value::Null, Value::Object(Map::new())],
+    }
+}
+
+fn remove_elements(value: &Value, start: usize, count: usize) -> Value {
+    let mut array = value.as_array().cloned().unwrap_or_default();
+    let end = (start + count).min(array.len());
+    array.drain(start..end);
+    Value::Array(array)
+}
+
+fn remove_fields(value: &Value, start: usize, count: usize) -> Value {
+    let object = value.as_object().cloned().unwrap_or_default();
+    Value::Object(
+        object
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| *i < start || *i >= start + count)
+            .map(|(_, field)| field)
+            .collect(),
+    )
+}
+
+fn remove_chars(value: &Value, start: usize, count: usize) -> Value {
+    let string = value.as_str().unwrap_or_default();
+    Value::String(
+        string
+            .chars()
+            .enumerate()
+            .filter(|(i, _)| *i < start || *i >= start + count)
+            .map(|(_, c)| c)
+            .collect(),
+    )
+}
+
+/// Escapes an object key for use in a JSON pointer.
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(all(test, not(fuzzing)))]
+mod tests {
+    use serde_json::json;
+
+    use super::minimize;
+
+    #[test]
+    fn shrinks_to_the_failing_part() {
+        let input = json!({
+            "name": "a room with a long name",
+            "topic": "topic",
+            "initial_state": [
+                {"type": "m.room.topic", "state_key": "", "content": {"topic": "t"}},
+                {"type": "bad\u{0}type", "state_key": "key", "content": {"a": [1, 2, 3]}},
+                {"type": "m.room.name", "state_key": "", "content": {"name": "n"}},
+            ],
+        });
+
+        // Fails as long as some state event type contains a NUL
+        let minimised = minimize(input, |candidate| {
+            candidate["initial_state"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .any(|s| matches!(s["type"].as_str(), Some(t) if t.contains('\0')))
+        });
+
+        assert_eq!(minimised, json!({"initial_state": [{"type": "\u{0}"}]}));
+    }
+}
@@ -0,0 +1,445 @@
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+
+/// The standard error response of the client-server API.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatrixError {
+    pub status: u16,
+    pub errcode: String,
+    pub error: Option<String>,
+    /// Any other fields of the error body, e.g. `retry_after_ms` or `soft_logout`.
+    pub extra: Map<String, Value>,
+}
+
+impl MatrixError {
+    /// Parses an error body. Returns `None` if `body` is not an object with a string `errcode`
+    /// and an optional string `error`.
+    pub fn from_body(status: StatusCode, body: &Value) -> Option<MatrixError> {
+        let mut extra = body.as_object()?.clone();
+        let errcode = match extra.remove("errcode")? {
+            Value::String(v) => v,
+            _ => return None,
+        };
+        let error = match extra.remove("error") {
+            None => None,
+            Some(Value::String(v)) => Some(v),
+            Some(_) => return None,
+        };
+        Some(MatrixError {
+            status: status.as_u16(),
+            errcode,
+            error,
+            extra,
+        })
+    }
+}
+
+impl fmt::Display for MatrixError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.status, self.errcode)?;
+        if let Some(error) = &self.error {
+            write!(f, ": {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Error codes for which the spec prescribes the HTTP status.
+const SPEC_STATUSES: &[(&str, &[u16])] = &[
+    ("M_FORBIDDEN", &[403]),
+    ("M_UNKNOWN_TOKEN", &[401]),
+    ("M_MISSING_TOKEN", &[401]),
+    ("M_BAD_JSON", &[400]),
+    ("M_NOT_JSON", &[400]),
+    ("M_NOT_FOUND", &[404]),
+    ("M_LIMIT_EXCEEDED", &[429]),
+    ("M_UNRECOGNIZED", &[404, 405]),
+    ("M_TOO_LARGE", &[413]),
+    ("M_INVALID_PARAM", &[400]),
+    ("M_MISSING_PARAM", &[400]),
+    ("M_ROOM_IN_USE", &[400]),
+    ("M_UNSUPPORTED_ROOM_VERSION", &[400]),
+    ("M_USER_DEACTIVATED", &[403]),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Success,
+    /// A well-formed error which is a legitimate answer to the input.
+    ExpectedRejection(MatrixError),
+    /// A well-formed error which is not a legitimate answer to the input.
+    UnexpectedRejection(MatrixError),
+    /// A request that must fail succeeded.
+    UnexpectedSuccess {
+        status: u16,
+        content: String,
+    },
+    SpecViolation {
+        error: MatrixError,
+        reason: String,
+    },
+    ServerError {
+        status: u16,
+        content: String,
+    },
+    MalformedErrorBody {
+        status: u16,
+        content: String,
+    },
+    NonJsonBody {
+        status: u16,
+        content: String,
+    },
+    UnexpectedStatus {
+        status: u16,
+        content: String,
+    },
+    TransportError(String),
+    /// The fuzzer could not send the request, e.g. because it was unable to renew its session or
+    /// to log in the identity the request is sent as. Says nothing about the input, so it is not
+    /// a finding.
+    FuzzerError(String),
+    /// The server did not answer within the deadline of the target. `server_alive` tells
+    /// whether it still answered the liveness probe afterwards, i.e. whether only the request
+    /// was slow or the whole server is wedged.
+    Hang {
+        deadline_ms: u64,
+        server_alive: bool,
+    },
+    /// The server let a request through which breaks an invariant across requests, e.g. a user
+    /// reading a room it is not allowed to see. `status` and `content` are the response to the
+    /// offending request.
+    InvariantViolation {
+        invariant: String,
+        status: u16,
+        content: String,
+    },
+    /// The server took much longer to answer than it usually does for the target, on the first
+    /// try and when the request was resent. Hints at blind injections and algorithmic
+    /// complexity bugs.
+    SlowResponse {
+        elapsed_ms: u64,
+        /// The median response time of the target.
+        baseline_ms: u64,
+    },
+    /// The server logged an error while handling the request although the response was fine,
+    /// e.g. an exception it caught and turned into a generic error. `log` is the log entry
+    /// including the traceback, `error` its exception or message.
+    LoggedError {
+        error: String,
+        log: String,
+    },
+    /// A metric of the server grew by more than its limit every time the request was sent,
+    /// e.g. memory or persisted events. `growth` is the smallest growth seen.
+    ResourceGrowth {
+        series: String,
+        growth: f64,
+    },
+    /// An instance of an already reported bug.
+    KnownBug {
+        bug: &'static str,
+        verdict: Box<Verdict>,
+    },
+}
+
+impl Verdict {
+    pub fn is_finding(&self) -> bool {
+        !matches!(
+            self,
+            Verdict::Success
+                | Verdict::ExpectedRejection(_)
+                | Verdict::FuzzerError(_)
+                | Verdict::KnownBug { .. }
+        )
+    }
+
+    /// Short name of the variant, e.g. for grouping findings.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Verdict::Success => "success",
+            Verdict::ExpectedRejection(_) => "expected_rejection",
+            Verdict::UnexpectedRejection(_) => "unexpected_rejection",
+            Verdict::UnexpectedSuccess { .. } => "unexpected_success",
+            Verdict::SpecViolation { .. } => "spec_violation",
+            Verdict::ServerError { .. } => "server_error",
+            Verdict::MalformedErrorBody { .. } => "malformed_error_body",
+            Verdict::NonJsonBody { .. } => "non_json_body",
+            Verdict::UnexpectedStatus { .. } => "unexpected_status",
+            Verdict::TransportError(_) => "transport_error",
+            Verdict::FuzzerError(_) => "fuzzer_error",
+            Verdict::Hang { .. } => "hang",
+            Verdict::InvariantViolation { .. } => "invariant_violation",
+            Verdict::SlowResponse { .. } => "slow_response",
+            Verdict::LoggedError { .. } => "logged_error",
+            Verdict::ResourceGrowth { .. } => "resource_growth",
+            Verdict::KnownBug { .. } => "known_bug",
+        }
+    }
+
+    /// The verdict without its known bug label.
+    pub fn unlabelled(&self) -> &Verdict {
+        match self {
+            Verdict::KnownBug { verdict, .. } => verdict,
+            verdict => verdict,
+        }
+    }
+
+    /// Whether `other` is a finding of the same kind, status and errcode. Known bug labels are
+    /// ignored.
+    pub fn same_finding(&self, other: &Verdict) -> bool {
+        let (this, other) = (self.unlabelled(), other.unlabelled());
+        other.is_finding()
+            && this.kind() == other.kind()
+            && this.status() == other.status()
+            && this.error().map(|e| &e.errcode) == other.error().map(|e| &e.errcode)
+    }
+
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Verdict::Success
+            | Verdict::TransportError(_)
+            | Verdict::FuzzerError(_)
+            | Verdict::Hang { .. }
+            | Verdict::SlowResponse { .. }
+            | Verdict::LoggedError { .. }
+            | Verdict::ResourceGrowth { .. } => None,
+            Verdict::ExpectedRejection(error)
+            | Verdict::UnexpectedRejection(error)
+            | Verdict::SpecViolation { error, .. } => Some(error.status),
+            Verdict::UnexpectedSuccess { status, .. }
+            | Verdict::ServerError { status, .. }
+            | Verdict::MalformedErrorBody { status, .. }
+            | Verdict::NonJsonBody { status, .. }
+            | Verdict::UnexpectedStatus { status, .. }
+            | Verdict::InvariantViolation { status, .. } => Some(*status),
+            Verdict::KnownBug { verdict, .. } => verdict.status(),
+        }
+    }
+
+    /// The response body, for verdicts which keep it.
+    pub fn content(&self) -> Option<&str> {
+        match self {
+            Verdict::UnexpectedSuccess { content, .. }
+            | Verdict::ServerError { content, .. }
+            | Verdict::MalformedErrorBody { content, .. }
+            | Verdict::NonJsonBody { content, .. }
+            | Verdict::UnexpectedStatus { content, .. }
+            | Verdict::InvariantViolation { content, .. } => Some(content),
+            Verdict::LoggedError { log, .. } => Some(log),
+            Verdict::KnownBug { verdict, .. } => verdict.content(),
+            _ => None,
+        }
+    }
+
+    pub fn error(&self) -> Option<&MatrixError> {
+        match self {
+            Verdict::ExpectedRejection(error)
+            | Verdict::UnexpectedRejection(error)
+            | Verdict::SpecViolation { error, .. } => Some(error),
+            Verdict::KnownBug { verdict, .. } => verdict.error(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Success => write!(f, "success"),
+            Verdict::ExpectedRejection(error) => write!(f, "expected rejection ({})", error),
+            Verdict::UnexpectedRejection(error) => write!(f, "unexpected rejection ({})", error),
+            Verdict::UnexpectedSuccess { status, content } => {
+                write!(f, "unexpected success ({}): {}", status, content)
+            }
+            Verdict::SpecViolation { error, reason } => {
+                write!(f, "spec violation ({}): {}", error, reason)
+            }
+            Verdict::ServerError { status, content } => {
+                write!(f, "server error ({}): {}", status, content)
+            }
+            Verdict::MalformedErrorBody { status, content } => {
+                write!(f, "malformed error body ({}): {}", status, content)
+            }
+            Verdict::NonJsonBody { status, content } => {
+                write!(f, "non-JSON body ({}): {}", status, content)
+            }
+            Verdict::UnexpectedStatus { status, content } => {
+                write!(f, "unexpected status ({}): {}", status, content)
+            }
+            Verdict::TransportError(e) => write!(f, "transport error: {}", e),
+            Verdict::FuzzerError(e) => write!(f, "fuzzer error: {}", e),
+            Verdict::Hang {
+                deadline_ms,
+                server_alive,
+            } => write!(
+                f,
+                "hang (no response within {}ms, {})",
+                deadline_ms,
+                if *server_alive {
+                    "the server still answers"
+                } else {
+                    "the server does not answer anymore"
+                }
+            ),
+            Verdict::InvariantViolation {
+                invariant,
+                status,
+                content,
+            } => write!(
+                f,
+                "invariant violation ({}, {}): {}",
+                invariant, status, content
+            ),
+            Verdict::SlowResponse {
+                elapsed_ms,
+                baseline_ms,
+            } => write!(
+                f,
+                "slow response ({} ms, usually {} ms)",
+                elapsed_ms, baseline_ms
+            ),
+            Verdict::LoggedError { error, .. } => write!(f, "logged error: {}", error),
+            Verdict::ResourceGrowth { series, growth } => {
+                write!(f, "resource growth ({} grew by {})", series, growth)
+            }
+            Verdict::KnownBug { bug, verdict } => write!(f, "known bug {} ({})", bug, verdict),
+        }
+    }
+}
+
+/// Checks the parts of an error response the spec is explicit about.
+pub fn spec_violation(error: &MatrixError) -> Option<String> {
+    let namespaced = !error.errcode.is_empty()
+        && error
+            .errcode
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_' || c == '.');
+    if !namespaced {
+        return Some(format!(
+            "errcode {:?} is not a namespaced code",
+            error.errcode
+        ));
+    }
+    if let Some((_, statuses)) = SPEC_STATUSES.iter().find(|(c, _)| *c == error.errcode) {
+        if !statuses.contains(&error.status) {
+            return Some(format!(
+                "{} must be sent with status {:?}",
+                error.errcode, statuses
+            ));
+        }
+    }
+    None
+}
+
+/// Classifies a response. `is_expected` decides whether a well-formed error is a legitimate
+/// answer to the input.
+pub fn classify(
+    status: StatusCode,
+    content: &str,
+    is_expected: impl Fn(&MatrixError) -> bool,
+) -> Verdict {
+    let code = status.as_u16();
+    let body: Value = match serde_json::from_str(content) {
+        Ok(v) => v,
+        Err(_) if status.is_server_error() => {
+            return Verdict::ServerError {
+                status: code,
+                content: content.to_string(),
+            }
+        }
+        Err(_) => {
+            return Verdict::NonJsonBody {
+                status: code,
+                content: content.to_string(),
+            }
+        }
+    };
+
+    if status.is_success() {
+        return Verdict::Success;
+    }
+    if status.is_server_error() {
+        return Verdict::ServerError {
+            status: code,
+            content: content.to_string(),
+        };
+    }
+    if !status.is_client_error() {
+        return Verdict::UnexpectedStatus {
+            status: code,
+            content: content.to_string(),
+        };
+    }
+
+    let error = match MatrixError::from_body(status, &body) {
+        Some(v) => v,
+        None => {
+            return Verdict::MalformedErrorBody {
+                status: code,
+                content: content.to_string(),
+            }
+        }
+    };
+    if let Some(reason) = spec_violation(&error) {
+        return Verdict::SpecViolation { error, reason };
+    }
+    if is_expected(&error) {
+        Verdict::ExpectedRejection(error)
+    } else {
+        Verdict::UnexpectedRejection(error)
+    }
+}
+
+#[cfg(all(test, not(fuzzing)))]
+mod tests {
+    use reqwest::StatusCode;
+
+    use super::{classify, MatrixError, Verdict};
+
+    fn room_in_use(error: &MatrixError) -> bool {
+        error.errcode == "M_ROOM_IN_USE" && error.status == 400
+    }
+
+    #[test]
+    fn classifies_by_errcode() {
+        let verdict = classify(
+            StatusCode::BAD_REQUEST,
+            r#"{"errcode": "M_ROOM_IN_USE", "error": "Room alias already taken"}"#,
+            room_in_use,
+        );
+        assert!(matches!(verdict, Verdict::ExpectedRejection(_)));
+
+        // Rewording the message does not matter, a different errcode does
+        let verdict = classify(
+            StatusCode::BAD_REQUEST,
+            r#"{"errcode": "M_UNKNOWN", "error": "M_ROOM_IN_USE"}"#,
+            room_in_use,
+        );
+        assert!(matches!(verdict, Verdict::UnexpectedRejection(_)));
+    }
+
+    #[test]
+    fn classifies_broken_responses() {
+        let verdict = classify(
+            StatusCode::FORBIDDEN,
+            r#"{"errcode": "M_ROOM_IN_USE"}"#,
+            room_in_use,
+        );
+        assert!(matches!(verdict, Verdict::SpecViolation { .. }));
+
+        let verdict = classify(StatusCode::BAD_REQUEST, r#"{"error": "nope"}"#, room_in_use);
+        assert!(matches!(verdict, Verdict::MalformedErrorBody { .. }));
+
+        let verdict = classify(StatusCode::BAD_REQUEST, "<html></html>", room_in_use);
+        assert!(matches!(verdict, Verdict::NonJsonBody { .. }));
+
+        let verdict = classify(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            r#"{"errcode": "M_UNKNOWN", "error": "Internal server error"}"#,
+            room_in_use,
+        );
+        assert!(matches!(verdict, Verdict::ServerError { .. }));
+    }
+}
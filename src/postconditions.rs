@@ -0,0 +1,319 @@
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Map, Value};
+use std::collections::HashSet;
+
+use crate::{
+    error::FuzzError,
+    identities::{self, Identity, Role},
+    oracle::Verdict,
+    types::{RoomVisibility, StateEvent},
+};
+
+/// State the server sends itself while creating a room, and which `initial_state` can not
+/// simply replace.
+const SERVER_STATE: &[&str] = &["m.room.create", "m.room.member", "m.room.power_levels"];
+
+/// Keys of `creation_content` the server overwrites.
+const SERVER_CREATION_KEYS: &[&str] = &["creator", "room_version"];
+
+/// State a createRoom request must have resulted in.
+#[derive(Debug)]
+struct Expected {
+    invariant: &'static str,
+    event_type: String,
+    state_key: String,
+    content: Value,
+    /// Whether the event's content has to equal `content`, or only contain its fields.
+    exact: bool,
+    /// Whether the event may be missing, as `content` is what the spec assumes without it.
+    default: bool,
+}
+
+/// Checks that the room created from `body` has the requested state, and returns a finding if
+/// the server dropped or changed part of the request.
+///
+/// Follows the precedence of the spec: `initial_state` overrides the events of the preset, and
+/// `name` and `topic` override `initial_state`. Failing to fetch the room's state skips the
+/// checks instead of reporting it.
+#[no_coverage]
+pub fn check_created_room(body: &Value, room_id: &str) -> Option<Verdict> {
+    match check(body, room_id) {
+        Ok(v) => v,
+        Err(e) => {
+            println!("Skipping the post-conditions of {} ({})", room_id, e);
+            None
+        }
+    }
+}
+
+#[no_coverage]
+fn check(body: &Value, room_id: &str) -> Result<Option<Verdict>, FuzzError> {
+    let owner = crate::identities().get(Role::Owner)?;
+    let path = format!("/_matrix/client/v3/rooms/{}/state", room_id);
+    let (events, content) = get::<Vec<StateEvent>>(&owner, &path)?;
+    for expected in expected_state(body) {
+        let actual = events
+            .iter()
+            .find(|e| e.type_ == expected.event_type && e.state_key == expected.state_key)
+            .map(|e| &e.content);
+        if !applied(&expected, actual) {
+            return Ok(Some(Verdict::InvariantViolation {
+                invariant: expected.invariant.to_string(),
+                status: 200,
+                content,
+            }));
+        }
+    }
+
+    if let Some(visibility @ ("public" | "private")) =
+        body.get("visibility").and_then(Value::as_str)
+    {
+        let path = format!("/_matrix/client/v3/directory/list/room/{}", room_id);
+        let (actual, content) = get::<RoomVisibility>(&owner, &path)?;
+        if actual.visibility != visibility {
+            return Ok(Some(Verdict::InvariantViolation {
+                invariant: "visibility was not applied".to_string(),
+                status: 200,
+                content,
+            }));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the decoded response and its body.
+#[no_coverage]
+fn get<T: DeserializeOwned>(identity: &Identity, path: &str) -> Result<(T, String), FuzzError> {
+    let config = crate::config()?;
+    let timeout = config.request_timeout();
+    let (status, content) = identities::send_as(identity, Method::GET, path, None, timeout)?;
+    if !status.is_success() {
+        return Err(FuzzError::Status {
+            url: config.url(path),
+            status: status.as_u16(),
+            body: content,
+        });
+    }
+    match serde_json::from_str(&content) {
+        Ok(v) => Ok((v, content)),
+        Err(source) => Err(FuzzError::Decode {
+            url: config.url(path),
+            source,
+        }),
+    }
+}
+
+fn expected_state(body: &Value) -> Vec<Expected> {
+    let mut expected: Vec<Expected> = Vec::new();
+    let mut initial_state = HashSet::new();
+    let name = body.get("name");
+    let topic = body.get("topic");
+
+    let events = body.get("initial_state").and_then(Value::as_array);
+    for event in events.into_iter().flatten() {
+        let (event_type, state_key) =
+            match (str_field(event, "type"), str_field(event, "state_key")) {
+                (Some(t), Some(k)) => (t, k),
+                _ => continue,
+            };
+        initial_state.insert((event_type, state_key));
+        // Later events of the same type and state key replace earlier ones
+        expected.retain(|e| e.event_type != event_type || e.state_key != state_key);
+        let content = match event.get("content") {
+            Some(content @ Value::Object(_)) => content.clone(),
+            _ => continue,
+        };
+        if SERVER_STATE.contains(&event_type)
+            || (event_type == "m.room.name" && name.is_some())
+            || (event_type == "m.room.topic" && topic.is_some())
+        {
+            continue;
+        }
+        expected.push(Expected {
+            invariant: "initial_state event was not applied",
+            event_type: event_type.to_string(),
+            state_key: state_key.to_string(),
+            content,
+            exact: true,
+            default: false,
+        });
+    }
+
+    let preset = match body.get("preset").and_then(Value::as_str) {
+        Some("public_chat") => Some(("public", "forbidden")),
+        Some("private_chat" | "trusted_private_chat") => Some(("invite", "can_join")),
+        _ => None,
+    };
+    if let Some((join_rule, guest_access)) = preset {
+        let mut preset = vec![
+            fields(
+                "preset was not applied",
+                "m.room.join_rules",
+                json!({ "join_rule": join_rule }),
+            ),
+            fields(
+                "preset was not applied",
+                "m.room.history_visibility",
+                json!({ "history_visibility": "shared" }),
+            ),
+            fields(
+                "preset was not applied",
+                "m.room.guest_access",
+                json!({ "guest_access": guest_access }),
+            ),
+        ];
+        // Without a guest access event guests are forbidden, so servers need not send one
+        preset[2].default = guest_access == "forbidden";
+        expected.extend(
+            preset
+                .into_iter()
+                .filter(|e| !initial_state.contains(&(e.event_type.as_str(), ""))),
+        );
+    }
+
+    // An empty name or topic may legitimately not be sent at all
+    match name {
+        Some(Value::String(name)) if !name.is_empty() => expected.push(fields(
+            "name was not applied",
+            "m.room.name",
+            json!({ "name": name }),
+        )),
+        _ => {}
+    }
+    match topic {
+        Some(Value::String(topic)) if !topic.is_empty() => expected.push(fields(
+            "topic was not applied",
+            "m.room.topic",
+            json!({ "topic": topic }),
+        )),
+        _ => {}
+    }
+
+    if let Some(Value::Object(creation_content)) = body.get("creation_content") {
+        let requested: Map<String, Value> = creation_content
+            .iter()
+            .filter(|(key, _)| !SERVER_CREATION_KEYS.contains(&key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        if !requested.is_empty() {
+            expected.push(fields(
+                "creation_content was not applied",
+                "m.room.create",
+                Value::Object(requested),
+            ));
+        }
+    }
+    expected
+}
+
+/// An expectation on some fields of a state event with an empty state key.
+fn fields(invariant: &'static str, event_type: &str, content: Value) -> Expected {
+    Expected {
+        invariant,
+        event_type: event_type.to_string(),
+        state_key: String::new(),
+        content,
+        exact: false,
+        default: false,
+    }
+}
+
+fn applied(expected: &Expected, actual: Option<&Value>) -> bool {
+    let actual = match actual {
+        Some(v) => v,
+        None => return expected.default,
+    };
+    if expected.exact {
+        return actual == &expected.content;
+    }
+    match expected.content.as_object() {
+        Some(fields) => fields
+            .iter()
+            .all(|(key, value)| actual.get(key) == Some(value)),
+        None => false,
+    }
+}
+
+fn str_field<'a>(content: &'a Value, key: &str) -> Option<&'a str> {
+    content.get(key).and_then(Value::as_str)
+}
+
+#[cfg(all(test, not(fuzzing)))]
+mod tests {
+    use serde_json::json;
+
+    use super::{applied, expected_state};
+
+    #[test]
+    fn follows_spec_precedence() {
+        let body = json!({
+            "preset": "public_chat",
+            "name": "Name",
+            "creation_content": {"creator": "@someone:else", "m.federate": false},
+            "initial_state": [
+                {"type": "m.room.name", "state_key": "", "content": {"name": "Overridden"}},
+                {"type": "m.room.join_rules", "state_key": "", "content": {"join_rule": "knock"}},
+                {"type": "m.room.member", "state_key": "@a:b", "content": {}},
+                {"type": "org.example", "state_key": "x", "content": {"a": 1}},
+                {"type": "org.example", "state_key": "x", "content": {"a": 2}},
+            ],
+        });
+        let expected = expected_state(&body);
+        let summary: Vec<_> = expected
+            .iter()
+            .map(|e| (e.event_type.as_str(), e.content.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("m.room.join_rules", json!({"join_rule": "knock"})),
+                ("org.example", json!({"a": 2})),
+                (
+                    "m.room.history_visibility",
+                    json!({"history_visibility": "shared"})
+                ),
+                ("m.room.guest_access", json!({"guest_access": "forbidden"})),
+                ("m.room.name", json!({"name": "Name"})),
+                ("m.room.create", json!({"m.federate": false})),
+            ]
+        );
+
+        let create = &expected[5];
+        let actual = json!({"creator": "@fuzzer:hs", "m.federate": false, "room_version": "10"});
+        assert!(applied(create, Some(&actual)));
+        assert!(!applied(create, Some(&json!({"creator": "@fuzzer:hs"}))));
+        assert!(!applied(&expected[1], Some(&json!({"a": 2, "b": 3}))));
+        assert!(!applied(&expected[1], None));
+
+        // Public rooms need not have a guest access event, private ones must allow guests
+        let guest_access = &expected[3];
+        assert!(applied(guest_access, None));
+        assert!(!applied(
+            guest_access,
+            Some(&json!({"guest_access": "can_join"}))
+        ));
+        let body = json!({
+            "preset": "private_chat",
+            "initial_state": [{
+                "type": "m.room.history_visibility",
+                "state_key": "",
+                "content": {"history_visibility": "joined"},
+            }],
+        });
+        let expected = expected_state(&body);
+        let summary: Vec<_> = expected.iter().map(|e| e.event_type.as_str()).collect();
+        let private = [
+            "m.room.history_visibility",
+            "m.room.join_rules",
+            "m.room.guest_access",
+        ];
+        assert_eq!(summary, private);
+        let guest_access = &expected[2];
+        assert!(!applied(guest_access, None));
+        assert!(applied(
+            guest_access,
+            Some(&json!({"guest_access": "can_join"}))
+        ));
+    }
+}
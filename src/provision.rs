@@ -0,0 +1,120 @@
+use hmac::{Hmac, Mac};
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::json;
+use sha1::Sha1;
+
+use crate::error::FuzzError;
+
+const REGISTER_PATH: &str = "/_synapse/admin/v1/register";
+
+/// A user to create through Synapse's shared secret registration.
+#[derive(Debug, Clone)]
+pub struct NewUser<'a> {
+    pub username: &'a str,
+    pub password: &'a str,
+    pub admin: bool,
+    /// E.g. `support` or `bot`. `None` creates a regular user.
+    pub user_type: Option<&'a str>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Registered {
+    pub user_id: String,
+    pub access_token: String,
+}
+
+#[derive(Deserialize)]
+struct Nonce {
+    nonce: String,
+}
+
+/// The MAC Synapse expects: HMAC-SHA1 keyed with `registration_shared_secret` over the
+/// NUL-separated nonce, username, password, admin flag and optional user type.
+pub fn registration_mac(secret: &str, nonce: &str, user: &NewUser) -> String {
+    let mut mac =
+        Hmac::<Sha1>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(nonce.as_bytes());
+    mac.update(b"\x00");
+    mac.update(user.username.as_bytes());
+    mac.update(b"\x00");
+    mac.update(user.password.as_bytes());
+    mac.update(b"\x00");
+    mac.update(if user.admin { b"admin" } else { b"notadmin" });
+    if let Some(user_type) = user.user_type {
+        mac.update(b"\x00");
+        mac.update(user_type.as_bytes());
+    }
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Creates `user` with the configured registration shared secret.
+#[no_coverage]
+pub fn register(user: &NewUser) -> Result<Registered, FuzzError> {
+    let config = crate::config()?;
+    let secret = config.registration_shared_secret()?;
+    let url = config.url(REGISTER_PATH);
+    let nonce: Nonce = crate::request_json(Method::GET, &url, None)?;
+    let mut body = json!({
+        "nonce": nonce.nonce,
+        "username": user.username,
+        "password": user.password,
+        "admin": user.admin,
+        "mac": registration_mac(secret, &nonce.nonce, user),
+    });
+    if let Some(user_type) = user.user_type {
+        body["user_type"] = json!(user_type);
+    }
+    crate::request_json(Method::POST, &url, Some(&body))
+}
+
+/// Creates `user` unless it already exists. Returns whether it was created.
+#[no_coverage]
+pub fn ensure_user(user: &NewUser) -> Result<bool, FuzzError> {
+    match register(user) {
+        Ok(_) => Ok(true),
+        Err(FuzzError::Status { body, .. }) if body.contains("M_USER_IN_USE") => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Creates the configured fuzzer user unless it already exists.
+#[no_coverage]
+pub fn ensure_fuzzer() -> Result<bool, FuzzError> {
+    let config = crate::config()?;
+    ensure_user(&NewUser {
+        username: config.username()?,
+        password: config.password()?,
+        admin: false,
+        user_type: None,
+    })
+}
+
+#[cfg(all(test, not(fuzzing)))]
+mod tests {
+    use super::{registration_mac, NewUser};
+
+    #[test]
+    fn computes_synapse_mac() {
+        let user = NewUser {
+            username: "fuzzer",
+            password: "password",
+            admin: false,
+            user_type: None,
+        };
+        assert_eq!(
+            registration_mac("secret", "nonce", &user),
+            "14a0832a2e57214b4af9f678b8fa3485bccfb429"
+        );
+
+        let admin = NewUser {
+            admin: true,
+            user_type: Some("bot"),
+            ..user
+        };
+        assert_eq!(
+            registration_mac("secret", "nonce", &admin),
+            "2b6ab6e6bed5bdae95fbeb3fd42cf9a430ef6a3a"
+        );
+    }
+}
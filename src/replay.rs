@@ -0,0 +1,272 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    findings::{self, Signature},
+    oracle::Verdict,
+    targets::RegisteredTarget,
+};
+
+/// Suffix of the file next to a saved input which records the verdict it originally caused.
+pub const EXPECTATION_SUFFIX: &str = ".verdict.json";
+
+/// The parts of a verdict which identify a finding.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Expectation {
+    pub kind: String,
+    pub status: Option<u16>,
+    pub errcode: Option<String>,
+}
+
+impl Expectation {
+    pub fn from_verdict(verdict: &Verdict) -> Expectation {
+        Expectation {
+            kind: verdict.kind().to_string(),
+            status: verdict.status(),
+            errcode: verdict.error().map(|e| e.errcode.clone()),
+        }
+    }
+}
+
+impl fmt::Display for Expectation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(status) = self.status {
+            write!(f, " {}", status)?;
+        }
+        if let Some(errcode) = &self.errcode {
+            write!(f, " {}", errcode)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The input still causes the same finding.
+    Reproduced,
+    /// The input no longer causes a finding.
+    Fixed,
+    /// The input causes a different finding than recorded.
+    Changed {
+        before: Expectation,
+        after: Expectation,
+    },
+    /// The input could not be sent.
+    Invalid(String),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Reproduced => write!(f, "reproduced"),
+            Outcome::Fixed => write!(f, "fixed"),
+            Outcome::Changed { before, after } => write!(f, "changed ({} -> {})", before, after),
+            Outcome::Invalid(e) => write!(f, "invalid ({})", e),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ReplayResult {
+    pub path: PathBuf,
+    pub verdict: Option<Verdict>,
+    pub outcome: Outcome,
+}
+
+pub fn expectation_path(input: &Path) -> PathBuf {
+    let mut name = input.file_name().unwrap_or_default().to_os_string();
+    name.push(EXPECTATION_SUFFIX);
+    input.with_file_name(name)
+}
+
+/// Expectations, finding records and bucket descriptions live next to the inputs but are not
+/// inputs themselves.
+fn is_metadata(path: &Path) -> bool {
+    path.to_string_lossy().ends_with(EXPECTATION_SUFFIX)
+        || path.to_string_lossy().ends_with(findings::RECORD_SUFFIX)
+        || path.file_name() == Some(findings::BUCKET_FILE.as_ref())
+}
+
+pub fn read_expectation(input: &Path) -> Option<Expectation> {
+    let content = fs::read(expectation_path(input)).ok()?;
+    serde_json::from_slice(&content).ok()
+}
+
+pub fn write_expectation(input: &Path, verdict: &Verdict) -> io::Result<()> {
+    let json = serde_json::to_vec_pretty(&Expectation::from_verdict(verdict.unlabelled()))?;
+    fs::write(expectation_path(input), json)
+}
+
+/// Sends a saved input to `target`.
+///
+/// With `raw` the file is sent as request body as is, otherwise it is parsed as the target's
+/// input and sanitised like during fuzzing.
+pub fn send_input(target: &RegisteredTarget, data: &[u8], raw: bool) -> Result<Verdict, String> {
+    if raw {
+        let body: Value = serde_json::from_slice(data).map_err(|e| e.to_string())?;
+        return Ok((target.run_body)(&body));
+    }
+    (target.run_json)(data).ok_or_else(|| format!("not a valid {} input", target.name))
+}
+
+/// Replays a single input and compares the result with its recorded expectation.
+///
+/// Inputs without an expectation count as reproduced as long as they cause any finding.
+pub fn replay_file(target: &RegisteredTarget, path: &Path, raw: bool) -> ReplayResult {
+    let verdict = fs::read(path)
+        .map_err(|e| e.to_string())
+        .and_then(|data| send_input(target, &data, raw))
+        // Says nothing about whether the input still causes its finding
+        .and_then(|verdict| match verdict {
+            Verdict::FuzzerError(e) => Err(e),
+            verdict => Ok(verdict),
+        });
+    let verdict = match verdict {
+        Ok(v) => v,
+        Err(e) => {
+            return ReplayResult {
+                path: path.to_path_buf(),
+                verdict: None,
+                outcome: Outcome::Invalid(e),
+            }
+        }
+    };
+
+    // A known bug is still a bug as far as verifying fixes is concerned
+    let outcome = if !verdict.unlabelled().is_finding() {
+        Outcome::Fixed
+    } else {
+        let after = Expectation::from_verdict(verdict.unlabelled());
+        match read_expectation(path) {
+            Some(before) if before != after => Outcome::Changed { before, after },
+            _ => Outcome::Reproduced,
+        }
+    };
+    ReplayResult {
+        path: path.to_path_buf(),
+        verdict: Some(verdict),
+        outcome,
+    }
+}
+
+/// Replays every input in `dir` and its subdirectories, like the buckets of a findings
+/// directory, skipping the metadata stored next to inputs.
+pub fn replay_dir(
+    target: &RegisteredTarget,
+    dir: &Path,
+    raw: bool,
+) -> io::Result<Vec<ReplayResult>> {
+    let mut paths = Vec::new();
+    collect_inputs(dir, &mut paths)?;
+    paths.sort();
+    Ok(paths
+        .iter()
+        .map(|path| replay_file(target, path, raw))
+        .collect())
+}
+
+fn collect_inputs(dir: &Path, paths: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_inputs(&path, paths)?;
+        } else if path.is_file() && !is_metadata(&path) {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Replays every input below `dir` and groups them by the signature of the finding they cause
+/// now. Inputs which no longer cause a finding are grouped by verdict kind, inputs which could
+/// not be sent under `invalid_input`.
+pub fn triage(target: &RegisteredTarget, dir: &Path) -> io::Result<BTreeMap<String, Vec<PathBuf>>> {
+    let mut groups: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for result in replay_dir(target, dir, false)? {
+        let group = match &result.verdict {
+            Some(verdict) if verdict.unlabelled().is_finding() => {
+                let signature = Signature::of(target.name, verdict);
+                format!("{} {}", signature.id(), signature)
+            }
+            Some(verdict) => verdict.kind().to_string(),
+            None => "invalid_input".to_string(),
+        };
+        groups.entry(group).or_default().push(result.path);
+    }
+    Ok(groups)
+}
+
+#[cfg(all(test, not(fuzzing)))]
+mod tests {
+    use reqwest::Method;
+    use serde_json::json;
+    use std::{env, fs, process};
+
+    use super::triage;
+    use crate::{
+        findings::{self, Request},
+        oracle::Verdict,
+        targets::RegisteredTarget,
+    };
+
+    fn server_error() -> Verdict {
+        Verdict::ServerError {
+            status: 500,
+            content: r#"{"errcode": "M_UNKNOWN", "error": "Internal server error"}"#.to_string(),
+        }
+    }
+
+    #[test]
+    fn triages_a_findings_directory() {
+        let target = RegisteredTarget {
+            name: "createRoom",
+            method: Method::POST,
+            path: "/_matrix/client/v3/createRoom",
+            requires_auth: true,
+            fuzzcheck_test: "tests::fuzz_create_room",
+            run_json: |data| {
+                let body: serde_json::Value = serde_json::from_slice(data).ok()?;
+                Some(match body.get("name") {
+                    Some(_) => server_error(),
+                    None => Verdict::Success,
+                })
+            },
+            run_body: |_| Verdict::Success,
+            probe_latency: |_| Vec::new(),
+            normalise: Ok,
+            from_bytes: |_| None,
+        };
+        let dir = env::temp_dir().join(format!("matrix-fuzz-triage-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for body in [
+            json!({"name": "a"}),
+            json!({"name": "b"}),
+            json!({"topic": "c"}),
+        ] {
+            let request = Request {
+                target: target.name.to_string(),
+                method: "POST".to_string(),
+                path: target.path.to_string(),
+                body,
+            };
+            findings::record(&dir, &request, &server_error()).unwrap();
+        }
+
+        let groups = triage(&target, &dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let signature = findings::Signature::of(target.name, &server_error());
+        let still_failing = &groups[&format!("{} {}", signature.id(), signature)];
+        assert_eq!(still_failing.len(), 2);
+        assert!(still_failing
+            .iter()
+            .all(|p| p.extension() == Some("json".as_ref())));
+        assert_eq!(groups["success"].len(), 1);
+        assert_eq!(groups.len(), 2);
+    }
+}
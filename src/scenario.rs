@@ -0,0 +1,309 @@
+use reqwest::Method;
+use serde_json::{json, Value};
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    authz,
+    error::FuzzError,
+    feedback,
+    identities::{self, Identity, Role},
+    oracle::{self, Verdict},
+    postconditions, session,
+    targets::{self, CreateRoom, FuzzTarget, Scenario},
+    types::scenario::{Action, ScenarioJSON},
+};
+
+static TXN_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// What earlier steps produced, for later steps to refer to.
+#[derive(Debug, Default)]
+struct Results {
+    rooms: Vec<String>,
+    events: Vec<String>,
+}
+
+impl Results {
+    fn room(&self, index: usize) -> Option<&str> {
+        pick(&self.rooms, index)
+    }
+
+    fn event(&self, index: usize) -> Option<&str> {
+        pick(&self.events, index)
+    }
+}
+
+fn pick(values: &[String], index: usize) -> Option<&str> {
+    if values.is_empty() {
+        return None;
+    }
+    Some(&values[index % values.len()])
+}
+
+/// Runs a scenario and returns the first finding, or the verdict of creating the room if that
+/// did not succeed.
+///
+/// Steps after the first one only report server errors and malformed responses, as rejecting
+/// an action is a legitimate answer to most sequences. Steps whose references can not be
+/// resolved, or whose user is unavailable, are skipped. Once all steps ran, the authorization
+/// invariants are checked for every room if identities are enabled.
+///
+/// Also returns the response time of the slowest step. Checking the created room and the
+/// authorization invariants is not part of it.
+#[no_coverage]
+pub fn execute(scenario: &ScenarioJSON) -> (Verdict, Duration) {
+    let mut slowest = Duration::ZERO;
+    let verdict = run(scenario, &mut slowest);
+    (verdict, slowest)
+}
+
+#[no_coverage]
+fn run(scenario: &ScenarioJSON, slowest: &mut Duration) -> Verdict {
+    let config = match crate::config() {
+        Ok(v) => v,
+        Err(e) => return Verdict::FuzzerError(e.to_string()),
+    };
+    let body = serde_json::to_value(&scenario.create_room).unwrap();
+    let (verdict, content, elapsed) = match send(
+        CreateRoom::NAME,
+        Role::Owner,
+        Method::POST,
+        CreateRoom::PATH,
+        &body,
+        CreateRoom::classify,
+    ) {
+        Ok(v) => v,
+        Err(e) if targets::is_timeout(&e) => return targets::hang(config.deadline(Scenario::NAME)),
+        Err(e) => return targets::send_error(e),
+    };
+    *slowest = elapsed;
+    let verdict = targets::label_known_bug::<CreateRoom>(&body, verdict);
+    if verdict != Verdict::Success {
+        return verdict;
+    }
+
+    let mut results = Results::default();
+    if let Some(room_id) = string_field(&content, "room_id") {
+        if let Some(verdict) = postconditions::check_created_room(&body, &room_id) {
+            return verdict;
+        }
+        results.rooms.push(room_id);
+    }
+    for (index, action) in scenario.actions.iter().enumerate() {
+        let (method, path, body) = match request(&results, action) {
+            Some(v) => v,
+            None => continue,
+        };
+        let role = action.actor();
+        let endpoint = format!("{}/{}", action.name(), role);
+        let (verdict, content, elapsed) =
+            match send(&endpoint, role, method, &path, &body, |s, c| {
+                oracle::classify(s, c, |_| true)
+            }) {
+                Ok(v) => v,
+                Err(e) if targets::is_timeout(&e) => {
+                    println!("Step {} ({}) hung", index + 1, action.name());
+                    return targets::hang(config.deadline(Scenario::NAME));
+                }
+                Err(FuzzError::Network { url, source }) => {
+                    return Verdict::TransportError(format!("{} ({})", url, source))
+                }
+                // E.g. guest access is disabled on the server
+                Err(_) => continue,
+            };
+        *slowest = (*slowest).max(elapsed);
+        if verdict.is_finding() {
+            println!("Step {} ({}) failed: {}", index + 1, action.name(), verdict);
+            return verdict;
+        }
+        if verdict != Verdict::Success {
+            continue;
+        }
+        if let Some(event_id) = string_field(&content, "event_id") {
+            results.events.push(event_id);
+        }
+        if let Some(room_id) = string_field(&content, "replacement_room") {
+            results.rooms.push(room_id);
+        }
+    }
+
+    if config.identities.enabled {
+        for room_id in &results.rooms {
+            if let Some(verdict) = authz::check_room(room_id) {
+                return verdict;
+            }
+        }
+    }
+    Verdict::Success
+}
+
+/// The request of a step. Returns `None` if the step refers to something which does not exist.
+#[no_coverage]
+fn request(results: &Results, action: &Action) -> Option<(Method, String, Value)> {
+    let rooms = "/_matrix/client/v3/rooms";
+    let request = match action {
+        Action::Invite { room, invitee, .. } => {
+            let invitee = crate::identities().get(*invitee).ok()?;
+            (
+                Method::POST,
+                format!("{}/{}/invite", rooms, encode(results.room(*room)?)),
+                json!({ "user_id": invitee.user_id }),
+            )
+        }
+        Action::Join { room, .. } => (
+            Method::POST,
+            format!("{}/{}/join", rooms, encode(results.room(*room)?)),
+            json!({}),
+        ),
+        Action::Leave { room, .. } => (
+            Method::POST,
+            format!("{}/{}/leave", rooms, encode(results.room(*room)?)),
+            json!({}),
+        ),
+        Action::SendState { room, event, .. } => (
+            Method::PUT,
+            format!(
+                "{}/{}/state/{}/{}",
+                rooms,
+                encode(results.room(*room)?),
+                encode(&event._type),
+                encode(&event.state_key)
+            ),
+            event.content.clone(),
+        ),
+        Action::SendMessage { room, body, .. } => (
+            Method::PUT,
+            format!(
+                "{}/{}/send/m.room.message/{}",
+                rooms,
+                encode(results.room(*room)?),
+                txn_id()
+            ),
+            json!({ "msgtype": "m.text", "body": body }),
+        ),
+        Action::Redact {
+            room,
+            event,
+            reason,
+            ..
+        } => {
+            let mut body = json!({});
+            if let Some(reason) = reason {
+                body["reason"] = json!(reason);
+            }
+            (
+                Method::PUT,
+                format!(
+                    "{}/{}/redact/{}/{}",
+                    rooms,
+                    encode(results.room(*room)?),
+                    encode(results.event(*event)?),
+                    txn_id()
+                ),
+                body,
+            )
+        }
+        Action::Upgrade {
+            room, new_version, ..
+        } => (
+            Method::POST,
+            format!("{}/{}/upgrade", rooms, encode(results.room(*room)?)),
+            json!({ "new_version": new_version }),
+        ),
+    };
+    Some(request)
+}
+
+/// Sends a step as the user of `role` and classifies the response. The owner's session is
+/// renewed like for single requests, see [`targets::send_request`]. `endpoint` names the step
+/// for [`feedback::observe_response`]. Every step has the deadline of the scenario target.
+///
+/// Also returns how long the server took to answer the step.
+#[no_coverage]
+fn send(
+    endpoint: &str,
+    role: Role,
+    method: Method,
+    path: &str,
+    body: &Value,
+    classify: impl Fn(reqwest::StatusCode, &str) -> Verdict,
+) -> Result<(Verdict, Value, Duration), FuzzError> {
+    let exchange = |identity: &Identity| -> Result<(Verdict, Value, Duration), FuzzError> {
+        let deadline = crate::config()?.deadline(Scenario::NAME);
+        let start = Instant::now();
+        let (status, content) =
+            identities::send_as(identity, method.clone(), path, Some(body), deadline)?;
+        let elapsed = start.elapsed();
+        feedback::observe_response(endpoint, status, &content);
+        let parsed = serde_json::from_str(&content).unwrap_or(Value::Null);
+        Ok((classify(status, &content), parsed, elapsed))
+    };
+    let result = exchange(&crate::identities().get(role)?)?;
+    match session::logged_out(&result.0) {
+        Some(soft_logout) if role == Role::Owner => {
+            crate::session().renew(soft_logout)?;
+            exchange(&crate::identities().get(role)?)
+        }
+        _ => Ok(result),
+    }
+}
+
+fn string_field(content: &Value, key: &str) -> Option<String> {
+    content.get(key)?.as_str().map(str::to_string)
+}
+
+/// A transaction ID which does not repeat across runs.
+fn txn_id() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis());
+    format!(
+        "fuzz{}-{}",
+        now,
+        TXN_COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Percent-encodes a path segment. Fuzzed state keys and event types may contain anything.
+fn encode(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+#[cfg(all(test, not(fuzzing)))]
+mod tests {
+    use super::{encode, pick};
+    use crate::{identities::Role, types::scenario::Action};
+
+    #[test]
+    fn resolves_references() {
+        assert_eq!(encode("!room:example.org"), "%21room%3Aexample.org");
+        assert_eq!(encode("a b/ä"), "a%20b%2F%C3%A4");
+
+        let rooms = vec!["!a:hs".to_string(), "!b:hs".to_string()];
+        assert_eq!(pick(&rooms, 3), Some("!b:hs"));
+        assert_eq!(pick(&[], 3), None);
+
+        let action: Action = serde_json::from_str(
+            r#"{"action": "invite", "actor": "owner", "room": 0, "invitee": "stranger"}"#,
+        )
+        .unwrap();
+        assert_eq!(action.actor(), Role::Owner);
+        assert!(matches!(
+            action,
+            Action::Invite {
+                invitee: Role::Stranger,
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,136 @@
+use serde::Deserialize;
+use std::{cmp::Ordering, fmt};
+
+/// Implementation and version of the fuzzed homeserver as reported by
+/// `/_matrix/federation/v1/version`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServerVersion {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Deserialize)]
+struct VersionResponse {
+    server: ServerVersion,
+}
+
+impl ServerVersion {
+    #[no_coverage]
+    pub fn fetch() -> Option<ServerVersion> {
+        let resp = crate::client()
+            .ok()?
+            .get(crate::config().ok()?.url("/_matrix/federation/v1/version"))
+            .send()
+            .ok()?;
+        let resp: VersionResponse = resp.json().ok()?;
+        Some(resp.server)
+    }
+
+    pub fn is(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+    }
+
+    pub fn parsed(&self) -> Version {
+        Version::parse(&self.version)
+    }
+}
+
+impl fmt::Display for ServerVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.name, self.version)
+    }
+}
+
+/// A loosely parsed release version like `1.66.0` or `1.66.0rc2`.
+///
+/// Anything after the numeric components counts as a pre-release, so `1.66.0rc2` sorts before
+/// `1.66.0`. Trailing build info such as ` (b=develop,abc123)` is ignored. Missing components
+/// count as 0, so `1.66` equals `1.66.0`.
+#[derive(Debug, Clone)]
+pub struct Version {
+    pub parts: Vec<u64>,
+    pub pre: Option<String>,
+}
+
+impl Version {
+    pub fn parse(s: &str) -> Version {
+        let s = s.trim().trim_start_matches('v');
+        let s = s.split_whitespace().next().unwrap_or("");
+        let mut parts = Vec::new();
+        let mut rest = s;
+        loop {
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            if end == 0 {
+                break;
+            }
+            parts.push(rest[..end].parse().unwrap_or(u64::MAX));
+            rest = &rest[end..];
+            match rest.strip_prefix('.') {
+                Some(r) if r.starts_with(|c: char| c.is_ascii_digit()) => rest = r,
+                _ => break,
+            }
+        }
+        let pre = rest.trim_start_matches(['-', '.', '+']);
+        Version {
+            parts,
+            pre: (!pre.is_empty()).then(|| pre.to_string()),
+        }
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let len = self.parts.len().max(other.parts.len());
+        for i in 0..len {
+            let a = self.parts.get(i).copied().unwrap_or(0);
+            let b = other.parts.get(i).copied().unwrap_or(0);
+            match a.cmp(&b) {
+                Ordering::Equal => {}
+                ord => return ord,
+            }
+        }
+        match (&self.pre, &other.pre) {
+            (None, None) => Ordering::Equal,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(_), None) => Ordering::Less,
+            (Some(a), Some(b)) => split_pre(a).cmp(&split_pre(b)),
+        }
+    }
+}
+
+/// Splits a pre-release like `rc10` into its label and number, so `rc2` sorts before `rc10`.
+fn split_pre(pre: &str) -> (&str, Option<u64>) {
+    let (label, number) = pre.split_at(pre.trim_end_matches(|c: char| c.is_ascii_digit()).len());
+    (label, number.parse().ok())
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(all(test, not(fuzzing)))]
+mod tests {
+    use super::Version;
+
+    #[test]
+    fn orders_versions() {
+        let v = Version::parse;
+        assert_eq!(v("1.66"), v("1.66.0"));
+        assert!(v("1.66.0rc2") < v("1.66.0rc10"));
+        assert!(v("1.66.0rc10") < v("1.66.0"));
+        assert!(v("1.65.1") < v("v1.66.0 (b=develop,abc123)"));
+        assert_ne!(v("1.66.0rc1"), v("1.66.0"));
+    }
+}
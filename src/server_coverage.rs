@@ -0,0 +1,45 @@
+use reqwest::Method;
+use serde::Deserialize;
+
+use crate::{
+    error::FuzzError,
+    feedback::{self, Channel},
+};
+
+/// Response of the side channel of `dockerfiles/synapse_coverage.py`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Delta {
+    /// IDs of the arcs, pairs of lines in a function of Synapse, executed since the last fetch.
+    pub arcs: Vec<u64>,
+}
+
+/// Fetches the arcs executed since the last fetch, which the side channel then forgets.
+#[no_coverage]
+pub fn fetch(url: &str) -> Result<Delta, FuzzError> {
+    crate::request_json(Method::GET, url, None)
+}
+
+/// Feeds the arcs Synapse executed for the last input to fuzzcheck, so inputs reaching new code
+/// of the server are kept in the corpus.
+///
+/// Only fetches while fuzzcheck is recording. The first fetch of a campaign also contains the
+/// arcs of the requests sent before, e.g. logging in, which only makes the first input look a
+/// bit more interesting.
+#[no_coverage]
+pub fn observe() {
+    let url = match crate::config().map(|config| &config.server_coverage.url) {
+        Ok(Some(v)) => v,
+        _ => return,
+    };
+    if !feedback::recording() {
+        return;
+    }
+    match fetch(url) {
+        Ok(delta) => {
+            for arc in delta.arcs {
+                feedback::observe(Channel::PythonCoverage, arc);
+            }
+        }
+        Err(e) => println!("Unable to fetch the server coverage ({})", e),
+    }
+}
@@ -0,0 +1,232 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    thread,
+};
+
+use crate::oracle::Verdict;
+
+/// Most of the log read after a single request. A request causing more log than this is
+/// suspicious anyway, and the errors are usually at its start.
+const MAX_READ: u64 = 1024 * 1024;
+
+/// Prefixes of the exceptions of the database drivers Synapse uses.
+const SQL_DRIVERS: &[&str] = &["psycopg2.", "sqlite3."];
+
+/// A position in the homeserver log, taken before a request.
+#[derive(Debug)]
+pub struct Mark {
+    path: &'static Path,
+    offset: u64,
+}
+
+impl Mark {
+    /// The end of the configured log. `None` if no log is configured or it does not exist.
+    #[no_coverage]
+    pub fn now() -> Option<Mark> {
+        let path = crate::config().ok()?.server_log.path.as_deref()?;
+        let offset = fs::metadata(path).ok()?.len();
+        Some(Mark { path, offset })
+    }
+
+    /// Log written since the mark. Reads from the start if the log was rotated meanwhile.
+    #[no_coverage]
+    pub fn read(&self) -> io::Result<String> {
+        let mut file = File::open(self.path)?;
+        let offset = if file.metadata()?.len() < self.offset {
+            0
+        } else {
+            self.offset
+        };
+        file.seek(SeekFrom::Start(offset))?;
+        let mut content = Vec::new();
+        file.take(MAX_READ).read_to_end(&mut content)?;
+        Ok(String::from_utf8_lossy(&content).into_owned())
+    }
+}
+
+/// A log entry in the format of `dockerfiles/synapse_log.yaml`:
+/// `<time> - <logger> - <line> - <level> - <request>- <message>`, followed by continuation
+/// lines like a traceback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub logger: String,
+    pub level: String,
+    /// The request the entry was logged for, e.g. `POST-42`. Empty outside of requests.
+    pub request: String,
+    pub message: String,
+    /// The whole entry as logged.
+    pub text: String,
+}
+
+impl Entry {
+    fn parse(text: &str) -> Entry {
+        let header = text.lines().next().unwrap_or_default();
+        let parts: Vec<&str> = header.splitn(5, " - ").collect();
+        let (logger, level, rest) = match parts[..] {
+            [_, logger, _, level, rest] => (logger, level, rest),
+            _ => ("", "", header),
+        };
+        let (request, message) = rest.split_once("- ").unwrap_or(("", rest));
+        Entry {
+            logger: logger.to_string(),
+            level: level.to_string(),
+            request: request.trim().to_string(),
+            message: message.to_string(),
+            text: text.to_string(),
+        }
+    }
+
+    /// Whether the entry reports an error: a traceback, an unhandled error or an exception of
+    /// a database driver.
+    pub fn is_error(&self) -> bool {
+        self.text.contains("Traceback (most recent call last)")
+            || self.text.contains("Unhandled error")
+            || SQL_DRIVERS.iter().any(|driver| {
+                self.text.match_indices(driver).any(|(i, _)| {
+                    let name = self.text[i + driver.len()..]
+                        .split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+                        .next()
+                        .unwrap_or_default();
+                    name.starts_with("errors.") || name.contains("Error")
+                })
+            })
+    }
+
+    /// The exception a traceback ends with, or the message of the entry.
+    pub fn error(&self) -> String {
+        // Skips the header. The frames of a traceback are indented, its exception is not.
+        let exception = self
+            .text
+            .lines()
+            .skip(1)
+            .filter(|line| !line.trim().is_empty() && !line.starts_with(char::is_whitespace))
+            .last();
+        match exception {
+            Some(exception) => exception.trim().to_string(),
+            None => self.message.trim().to_string(),
+        }
+    }
+
+    fn is_access(&self) -> bool {
+        self.logger.starts_with("synapse.access")
+    }
+}
+
+/// Splits log text into entries. Lines not starting with a timestamp belong to the entry
+/// before them.
+pub fn parse(log: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut current = String::new();
+    for line in log.lines() {
+        if starts_entry(line) && !current.is_empty() {
+            entries.push(Entry::parse(&current));
+            current.clear();
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+    if !current.is_empty() {
+        entries.push(Entry::parse(&current));
+    }
+    entries
+}
+
+/// Whether `line` starts with a date like `2022-03-04`.
+fn starts_entry(line: &str) -> bool {
+    let bytes = line.as_bytes();
+    bytes.len() >= 10
+        && bytes[..4].iter().all(u8::is_ascii_digit)
+        && bytes[4] == b'-'
+        && bytes[5..7].iter().all(u8::is_ascii_digit)
+        && bytes[7] == b'-'
+        && bytes[8..10].iter().all(u8::is_ascii_digit)
+}
+
+/// The error entries logged for the fuzzer's requests.
+///
+/// The fuzzer's requests are the ones whose access log line carries its user agent. Errors of
+/// other requests and background tasks are ignored, unless the log has no access lines at all,
+/// in which case every error counts.
+pub fn errors(entries: &[Entry]) -> Vec<&Entry> {
+    let access: Vec<&Entry> = entries.iter().filter(|e| e.is_access()).collect();
+    let ours: HashSet<&str> = access
+        .iter()
+        .filter(|e| e.message.contains(crate::USER_AGENT))
+        .map(|e| e.request.as_str())
+        .collect();
+    entries
+        .iter()
+        .filter(|e| e.is_error() && (access.is_empty() || ours.contains(e.request.as_str())))
+        .collect()
+}
+
+/// Looks for errors the server logged since `mark` and returns [`Verdict::LoggedError`] for
+/// the first one, unless `verdict` already is a finding. Then the log entry is only printed.
+#[no_coverage]
+pub fn check(mark: Option<Mark>, verdict: Verdict) -> Verdict {
+    let (mark, config) = match (mark, crate::config()) {
+        (Some(mark), Ok(config)) => (mark, config),
+        _ => return verdict,
+    };
+    thread::sleep(config.server_log.settle());
+    let log = match mark.read() {
+        Ok(v) => v,
+        Err(e) => {
+            println!("Unable to read the server log ({})", e);
+            return verdict;
+        }
+    };
+    let entries = parse(&log);
+    let entry = match errors(&entries).first() {
+        Some(v) => (*v).clone(),
+        None => return verdict,
+    };
+    if verdict.is_finding() {
+        println!("The server logged:\n{}", entry.text);
+        return verdict;
+    }
+    Verdict::LoggedError {
+        error: entry.error(),
+        log: entry.text,
+    }
+}
+
+#[cfg(all(test, not(fuzzing)))]
+mod tests {
+    use super::{errors, parse};
+
+    const LOG: &str = "\
+2022-03-04 10:00:00,001 - synapse.handlers.room - 123 - ERROR - POST-7- Failed to create room
+Traceback (most recent call last):
+  File \"/synapse/handlers/room.py\", line 123, in create_room
+    await self.store.store_room()
+psycopg2.errors.UntranslatableCharacter: unsupported Unicode escape sequence
+2022-03-04 10:00:00,002 - synapse.access.http.8008 - 450 - INFO - POST-7- 172.18.0.1 - 8008 - {@fuzzer:localhost} Processed request: 0.010sec 56B 200 \"POST /_matrix/client/v3/createRoom HTTP/1.1\" \"synapse-fuzzer\" [0 dbevts]
+2022-03-04 10:00:00,003 - synapse.federation - 99 - ERROR - - Unhandled error in background process
+2022-03-04 10:00:00,004 - synapse.access.http.8008 - 450 - INFO - GET-8- 172.18.0.1 - 8008 - {None} Processed request: 0.001sec 20B 200 \"GET /health HTTP/1.1\" \"curl\" [0 dbevts]
+2022-03-04 10:00:00,005 - synapse.storage - 12 - WARNING - GET-8- sqlite3.OperationalError: database is locked
+";
+
+    #[test]
+    fn finds_errors_of_fuzzer_requests() {
+        let entries = parse(LOG);
+        assert_eq!(entries.len(), 5);
+        assert_eq!(entries[0].request, "POST-7");
+        assert_eq!(entries[0].level, "ERROR");
+        assert_eq!(entries[2].request, "");
+        assert!(entries[2].is_error() && entries[4].is_error());
+        assert!(!entries[1].is_error());
+
+        let errors = errors(&entries);
+        assert_eq!(errors, vec![&entries[0]]);
+        assert_eq!(
+            errors[0].error(),
+            "psycopg2.errors.UntranslatableCharacter: unsupported Unicode escape sequence"
+        );
+    }
+}
@@ -0,0 +1,236 @@
+use hmac::{Hmac, Mac};
+use reqwest::Method;
+use serde_json::{json, Value};
+use sha2::Sha512;
+use std::{
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::FuzzError,
+    oracle::Verdict,
+    provision,
+    types::{LoginGet, LoginPost, RefreshPost},
+};
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "token_auth")] {
+        /// Login type of the shared secret auth module
+        /// (https://github.com/devture/matrix-synapse-shared-secret-auth).
+        pub const LOGIN_TYPE: &str = "com.devture.shared_secret_auth";
+    } else {
+        pub const LOGIN_TYPE: &str = "m.login.password";
+    }
+}
+
+/// Tokens are refreshed this long before they expire, so requests in flight do not fail.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(5);
+
+struct Tokens {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_at: Option<Instant>,
+}
+
+impl Tokens {
+    fn new(
+        access_token: String,
+        refresh_token: Option<String>,
+        expires_in_ms: Option<u64>,
+    ) -> Self {
+        Tokens {
+            access_token,
+            refresh_token,
+            expires_at: expires_in_ms.map(|ms| Instant::now() + Duration::from_millis(ms)),
+        }
+    }
+
+    fn expired(&self) -> bool {
+        matches!(self.expires_at, Some(at) if Instant::now() + EXPIRY_MARGIN >= at)
+    }
+}
+
+/// The fuzzer's login on the homeserver.
+///
+/// Logs in on first use and renews the session when it expires or the server rejects the
+/// access token, preferring the refresh token over logging in again. A failed login is not
+/// kept, so the next request tries again.
+#[derive(Default)]
+pub struct Session {
+    tokens: Mutex<Option<Tokens>>,
+}
+
+impl Session {
+    fn lock(&self) -> MutexGuard<'_, Option<Tokens>> {
+        self.tokens.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    #[no_coverage]
+    pub fn access_token(&self) -> Result<String, FuzzError> {
+        let mut tokens = self.lock();
+        if let Some(t) = &*tokens {
+            if !t.expired() {
+                return Ok(t.access_token.clone());
+            }
+        }
+        let renewed = renew(tokens.take(), true)?;
+        let access_token = renewed.access_token.clone();
+        *tokens = Some(renewed);
+        Ok(access_token)
+    }
+
+    /// Replaces the session after the server rejected its access token. After a soft logout
+    /// the refresh token may still be used, otherwise the fuzzer logs in again.
+    #[no_coverage]
+    pub fn renew(&self, soft_logout: bool) -> Result<(), FuzzError> {
+        let mut tokens = self.lock();
+        *tokens = Some(renew(tokens.take(), soft_logout)?);
+        Ok(())
+    }
+}
+
+/// Whether `verdict` means the access token is no longer valid. Returns the `soft_logout` flag
+/// of the error if so.
+pub fn logged_out(verdict: &Verdict) -> Option<bool> {
+    let error = verdict.unlabelled().error()?;
+    if error.errcode != "M_UNKNOWN_TOKEN" {
+        return None;
+    }
+    Some(error.extra.get("soft_logout") == Some(&json!(true)))
+}
+
+#[no_coverage]
+fn renew(old: Option<Tokens>, refreshable: bool) -> Result<Tokens, FuzzError> {
+    let refresh_token = old.and_then(|t| t.refresh_token);
+    if let (true, Some(refresh_token)) = (refreshable, refresh_token) {
+        match refresh(&refresh_token) {
+            Ok(v) => return Ok(v),
+            Err(e) => println!("Refreshing the session failed ({}), logging in again", e),
+        }
+    }
+    login()
+}
+
+#[no_coverage]
+fn refresh(refresh_token: &str) -> Result<Tokens, FuzzError> {
+    let url = crate::config()?.url("/_matrix/client/v3/refresh");
+    let body = json!({ "refresh_token": refresh_token });
+    let res: RefreshPost = crate::request_json(Method::POST, &url, Some(&body))?;
+    // The server may keep the refresh token valid instead of rotating it
+    let refresh_token = res
+        .refresh_token
+        .unwrap_or_else(|| refresh_token.to_string());
+    Ok(Tokens::new(
+        res.access_token,
+        Some(refresh_token),
+        res.expires_in_ms,
+    ))
+}
+
+#[no_coverage]
+fn login() -> Result<Tokens, FuzzError> {
+    let config = crate::config()?;
+    let url = config.url("/_matrix/client/v3/login");
+    let res: LoginGet = crate::request_json(Method::GET, &url, None)?;
+    if !res.flows.iter().any(|flow| flow.type_ == LOGIN_TYPE) {
+        return Err(FuzzError::LoginFlowUnavailable {
+            wanted: LOGIN_TYPE,
+            offered: res.flows.into_iter().map(|flow| flow.type_).collect(),
+        });
+    }
+
+    let mut body = login_body()?;
+    body["refresh_token"] = Value::Bool(true);
+    let res: LoginPost = match crate::request_json(Method::POST, &url, Some(&body)) {
+        // On a fresh homeserver the user does not exist yet
+        Err(FuzzError::Status { status: 403, .. })
+            if config.registration_shared_secret.is_some() =>
+        {
+            if provision::ensure_fuzzer()? {
+                println!("Created the user {}", config.username()?);
+            }
+            crate::request_json(Method::POST, &url, Some(&body))?
+        }
+        res => res?,
+    };
+    Ok(Tokens::new(
+        res.access_token,
+        res.refresh_token,
+        res.expires_in_ms,
+    ))
+}
+
+#[cfg(not(feature = "token_auth"))]
+fn login_body() -> Result<Value, FuzzError> {
+    let config = crate::config()?;
+    Ok(json!({
+        "type": LOGIN_TYPE,
+        "user": config.username()?,
+        "password": config.password()?,
+    }))
+}
+
+#[cfg(feature = "token_auth")]
+fn login_body() -> Result<Value, FuzzError> {
+    let config = crate::config()?;
+    let user_id = config.user_id()?;
+    Ok(json!({
+        "type": LOGIN_TYPE,
+        "identifier": {
+            "type": "m.id.user",
+            "user": user_id,
+        },
+        "token": shared_secret_token(config.shared_secret()?, &user_id),
+    }))
+}
+
+/// The login token the shared secret auth module expects: the hex encoded HMAC-SHA512 of the
+/// full user ID, keyed with the shared secret.
+pub fn shared_secret_token(secret: &str, user_id: &str) -> String {
+    let mut mac =
+        Hmac::<Sha512>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(user_id.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(all(test, not(fuzzing)))]
+mod tests {
+    use reqwest::StatusCode;
+
+    use super::{logged_out, shared_secret_token};
+    use crate::oracle::classify;
+
+    #[test]
+    fn detects_rejected_tokens() {
+        let verdict = classify(
+            StatusCode::UNAUTHORIZED,
+            r#"{"errcode": "M_UNKNOWN_TOKEN", "error": "Access token has expired", "soft_logout": true}"#,
+            |_| false,
+        );
+        assert_eq!(logged_out(&verdict), Some(true));
+
+        let verdict = classify(
+            StatusCode::UNAUTHORIZED,
+            r#"{"errcode": "M_UNKNOWN_TOKEN", "error": "Invalid access token"}"#,
+            |_| false,
+        );
+        assert_eq!(logged_out(&verdict), Some(false));
+
+        let verdict = classify(
+            StatusCode::FORBIDDEN,
+            r#"{"errcode": "M_FORBIDDEN"}"#,
+            |_| false,
+        );
+        assert_eq!(logged_out(&verdict), None);
+    }
+
+    #[test]
+    fn computes_shared_secret_token() {
+        assert_eq!(
+            shared_secret_token("secret", "@fuzzer:synapse"),
+            "4ca043476e81d9df5d513c71cb515ba57f9f5f7f57746b79e8ba4e007b15fd23\
+             36af63ae4b4b14f4705cf410733abbbb971a78f18c8efe9b3ba404899d9eebd9"
+        );
+    }
+}
@@ -0,0 +1,505 @@
+use arbitrary::{Arbitrary, Unstructured};
+use reqwest::{blocking::Response, Method, StatusCode};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    fmt::Debug,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::FuzzError,
+    feedback, findings,
+    identities::Role,
+    known_bugs,
+    latency::{self, Injection},
+    metrics,
+    oracle::{self, Verdict},
+    server_coverage, server_log, session,
+};
+
+pub mod create_room;
+pub mod login;
+pub mod scenario;
+
+pub use create_room::CreateRoom;
+pub use login::Login;
+pub use scenario::Scenario;
+
+/// A single fuzzed endpoint.
+///
+/// The fuzzcheck tests, the AFL binaries and the CLI all drive a target through [`run`], so an
+/// implementation only has to describe the request and which error responses are fine.
+pub trait FuzzTarget {
+    type Input: Clone + Debug + Serialize + DeserializeOwned;
+
+    const NAME: &'static str;
+    const METHOD: Method;
+    /// Path of the endpoint. `{name}` placeholders are filled from [`FuzzTarget::path_params`].
+    const PATH: &'static str;
+    const REQUIRES_AUTH: bool;
+    /// Name of the test generated with [`fuzzcheck_target!`], as passed to `cargo fuzzcheck`.
+    const FUZZCHECK_TEST: &'static str;
+
+    /// Checks the settings the target needs, once before fuzzing starts, so the other methods
+    /// can rely on them.
+    fn check_config() -> Result<(), FuzzError> {
+        Ok(())
+    }
+
+    fn path_params(_input: &Self::Input) -> Vec<(&'static str, String)> {
+        Vec::new()
+    }
+
+    /// The user sending the request. Only used if [`FuzzTarget::REQUIRES_AUTH`] is set.
+    fn identity(_body: &serde_json::Value) -> Role {
+        Role::Owner
+    }
+
+    /// Adjusts the generated input before it is sent.
+    fn sanitise(input: &Self::Input) -> Self::Input {
+        input.clone()
+    }
+
+    fn classify(status: StatusCode, content: &str) -> Verdict {
+        classify_with_allowlist::<Self>(status, content)
+    }
+
+    /// Checks invariants beyond the response after the server accepted `body`, e.g. whether
+    /// other users can see a created room. Returns a finding if one is broken.
+    fn after_success(_body: &serde_json::Value, _response: &serde_json::Value) -> Option<Verdict> {
+        None
+    }
+
+    /// Sends `body` and asks the oracle about the response, see [`send_request`]. Targets which
+    /// are more than a single request, like [`Scenario`], replace this.
+    ///
+    /// Also returns the response time of the fuzzed request, which does not include checks
+    /// after the response like [`FuzzTarget::after_success`].
+    fn exchange(path: &str, body: &serde_json::Value) -> (Verdict, Duration)
+    where
+        Self: Sized,
+    {
+        send_request::<Self>(path, body)
+    }
+}
+
+/// Classifies a response, treating errors listed for the target in the allowlist file as
+/// expected.
+#[no_coverage]
+pub fn classify_with_allowlist<T: FuzzTarget + ?Sized>(
+    status: StatusCode,
+    content: &str,
+) -> Verdict {
+    let allowlist = match crate::allowlist() {
+        Ok(v) => v,
+        Err(e) => return Verdict::FuzzerError(e.to_string()),
+    };
+    oracle::classify(status, content, |error| {
+        allowlist
+            .find(T::NAME, crate::server_version().as_ref(), error)
+            .is_some()
+    })
+}
+
+#[no_coverage]
+pub fn path<T: FuzzTarget>(input: &T::Input) -> String {
+    let mut path = T::PATH.to_string();
+    for (name, value) in T::path_params(input) {
+        path = path.replace(&format!("{{{}}}", name), &value);
+    }
+    path
+}
+
+/// Sends a request to the target. Unlike the fuzzer's own requests this is never retried, and
+/// the whole exchange has to finish within the target's deadline.
+#[no_coverage]
+pub fn send<T: FuzzTarget>(path: &str, body: &serde_json::Value) -> Result<Response, FuzzError> {
+    let config = crate::config()?;
+    let url = config.url(path);
+    let mut request = crate::client()?
+        .request(T::METHOD, &url)
+        .timeout(config.deadline(T::NAME))
+        .json(body);
+    if T::REQUIRES_AUTH {
+        let identity = crate::identities().get(T::identity(body))?;
+        request = request.header("Authorization", format!("Bearer {}", identity.access_token));
+    }
+    request
+        .send()
+        .map_err(|source| FuzzError::Network { url, source })
+}
+
+/// Sanitises and sends `input`, then asks the oracle about the response.
+#[no_coverage]
+pub fn execute<T: FuzzTarget>(input: &T::Input) -> Verdict {
+    let input = T::sanitise(input);
+    let body = serde_json::to_value(&input).unwrap();
+    execute_body::<T>(&path::<T>(&input), &body)
+}
+
+/// Runs `body` without sanitising it first and labels known bugs in the verdict. Errors in
+/// the server log, growing metrics and slow responses are findings if they are watched for.
+#[no_coverage]
+pub fn execute_body<T: FuzzTarget>(path: &str, body: &serde_json::Value) -> Verdict {
+    let config = match crate::config() {
+        Ok(v) => v,
+        Err(e) => return Verdict::FuzzerError(e.to_string()),
+    };
+    let mark = server_log::Mark::now();
+    let (verdict, elapsed) = T::exchange(path, body);
+    let verdict = server_log::check(mark, verdict);
+    server_coverage::observe();
+    let verdict = metrics::check::<T>(path, body, verdict);
+    let verdict = if config.latency.enabled {
+        latency::check::<T>(path, body, verdict, elapsed)
+    } else {
+        verdict
+    };
+    label_known_bug::<T>(body, verdict)
+}
+
+/// Sends `body` and asks the oracle about the response. Returns the verdict and how long the
+/// server took to answer.
+///
+/// If the server rejects the fuzzer's access token the session is renewed and the request sent
+/// again, so an expired session does not end up as a finding.
+#[no_coverage]
+pub fn send_request<T: FuzzTarget>(path: &str, body: &serde_json::Value) -> (Verdict, Duration) {
+    let (verdict, elapsed) = send_once::<T>(path, body);
+    match session::logged_out(&verdict) {
+        Some(soft_logout) if T::REQUIRES_AUTH && T::identity(body) == Role::Owner => {
+            match crate::session().renew(soft_logout) {
+                Ok(()) => send_once::<T>(path, body),
+                Err(e) => (
+                    Verdict::FuzzerError(format!("unable to renew the session: {}", e)),
+                    elapsed,
+                ),
+            }
+        }
+        _ => (verdict, elapsed),
+    }
+}
+
+#[no_coverage]
+fn send_once<T: FuzzTarget>(path: &str, body: &serde_json::Value) -> (Verdict, Duration) {
+    let config = match crate::config() {
+        Ok(v) => v,
+        Err(e) => return (Verdict::FuzzerError(e.to_string()), Duration::ZERO),
+    };
+    let start = Instant::now();
+    let response = send::<T>(path, body).and_then(|resp| {
+        let status = resp.status();
+        let url = resp.url().to_string();
+        resp.text()
+            .map(|content| (status, content))
+            .map_err(|source| FuzzError::Network { url, source })
+    });
+    let elapsed = start.elapsed();
+    let (status, content) = match response {
+        Ok(v) => v,
+        Err(e) if is_timeout(&e) => return (hang(config.deadline(T::NAME)), elapsed),
+        Err(e) => return (send_error(e), elapsed),
+    };
+
+    feedback::observe_response(T::NAME, status, &content);
+    let verdict = T::classify(status, &content);
+    if verdict != Verdict::Success {
+        return (verdict, elapsed);
+    }
+    let verdict = match serde_json::from_str(&content) {
+        Ok(response) => T::after_success(body, &response).unwrap_or(verdict),
+        Err(_) => verdict,
+    };
+    (verdict, elapsed)
+}
+
+/// The verdict for a request which could not be sent. Only network errors are the server's
+/// doing; anything else, e.g. an identity which can not be logged in, is the fuzzer's.
+pub fn send_error(error: FuzzError) -> Verdict {
+    match error {
+        FuzzError::Network { .. } => Verdict::TransportError(error.to_string()),
+        _ => Verdict::FuzzerError(error.to_string()),
+    }
+}
+
+/// Whether a request failed because it missed its deadline.
+pub fn is_timeout(error: &FuzzError) -> bool {
+    matches!(error, FuzzError::Network { source, .. } if source.is_timeout())
+}
+
+/// The verdict for a request which missed `deadline`. Probes whether the server is still
+/// alive, so a slow request can be told from a wedged server.
+#[no_coverage]
+pub fn hang(deadline: Duration) -> Verdict {
+    Verdict::Hang {
+        deadline_ms: deadline.as_millis() as u64,
+        server_alive: crate::server_reachable(),
+    }
+}
+
+#[no_coverage]
+pub fn label_known_bug<T: FuzzTarget>(body: &serde_json::Value, verdict: Verdict) -> Verdict {
+    if !verdict.is_finding() {
+        return verdict;
+    }
+    match known_bugs::find(T::NAME, crate::server_version().as_ref(), body, &verdict) {
+        Some(bug) => Verdict::KnownBug {
+            bug: bug.id,
+            verdict: Box::new(verdict),
+        },
+        None => verdict,
+    }
+}
+
+/// Returns `false` if `input` caused a new finding.
+///
+/// Findings are stored in buckets below the configured findings directory. Further instances of
+/// an already found bug are only counted, so they do not stop a campaign, unless the server
+/// stopped answering.
+#[no_coverage]
+pub fn run<T: FuzzTarget>(input: &T::Input) -> bool {
+    let input = T::sanitise(input);
+    let request = findings::Request {
+        target: T::NAME.to_string(),
+        method: T::METHOD.to_string(),
+        path: path::<T>(&input),
+        body: serde_json::to_value(&input).unwrap(),
+    };
+    let verdict = execute_body::<T>(&request.path, &request.body);
+    // Not the input's fault, so skip it instead of recording a finding
+    if let Verdict::FuzzerError(e) = &verdict {
+        println!("{}: skipping an input, {}", T::NAME, e);
+        return true;
+    }
+    if !verdict.is_finding() {
+        return true;
+    }
+    let wedged = matches!(
+        verdict.unlabelled(),
+        Verdict::Hang {
+            server_alive: false,
+            ..
+        }
+    );
+    let findings = match crate::config() {
+        Ok(config) => &config.output.findings,
+        Err(e) => {
+            println!("{}: {} (unable to save finding: {})", T::NAME, verdict, e);
+            return false;
+        }
+    };
+    match findings::record(findings, &request, &verdict) {
+        Ok(recorded) if recorded.new => {
+            println!(
+                "{}: new finding {} ({})",
+                T::NAME,
+                verdict,
+                recorded.path.display()
+            );
+            false
+        }
+        // Every further input would hang as well
+        Ok(_) if wedged => {
+            println!("{}: the server does not answer anymore", T::NAME);
+            false
+        }
+        Ok(_) => true,
+        Err(e) => {
+            println!("{}: {} (unable to save finding: {})", T::NAME, verdict, e);
+            false
+        }
+    }
+}
+
+/// Turns raw fuzzer bytes into an input.
+///
+/// Bytes which are a valid JSON body of the request are used as is, so JSON seeds and findings
+/// can be fed to AFL. Anything else drives the input's `Arbitrary` impl.
+#[no_coverage]
+pub fn decode_bytes<T: FuzzTarget>(data: &[u8]) -> Option<T::Input>
+where
+    T::Input: for<'a> Arbitrary<'a>,
+{
+    match serde_json::from_slice::<T::Input>(data) {
+        Ok(v) => Some(v),
+        Err(_) => T::Input::arbitrary_take_rest(Unstructured::new(data)).ok(),
+    }
+}
+
+/// Entry point for the AFL binaries.
+#[no_coverage]
+pub fn run_afl<T: FuzzTarget>(data: &[u8])
+where
+    T::Input: for<'a> Arbitrary<'a>,
+{
+    if let Some(input) = decode_bytes::<T>(data) {
+        if !run::<T>(&input) {
+            panic!("{} found a failure", T::NAME);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RegisteredTarget {
+    pub name: &'static str,
+    pub method: Method,
+    pub path: &'static str,
+    pub requires_auth: bool,
+    /// Name of the fuzzcheck test generated by [`fuzzcheck_target!`].
+    pub fuzzcheck_test: &'static str,
+    /// Runs the target with a JSON encoded input. Returns `None` if the input does not parse.
+    pub run_json: fn(&[u8]) -> Option<Verdict>,
+    /// Sends a request body exactly as given, see [`execute_body`].
+    pub run_body: fn(&serde_json::Value) -> Verdict,
+    /// Injects time-based payloads into a request body, see [`latency::probe`].
+    pub probe_latency: fn(&serde_json::Value) -> Vec<Injection>,
+    /// Round trips a JSON value through the input type.
+    pub normalise: fn(serde_json::Value) -> Result<serde_json::Value, String>,
+    /// Decodes raw fuzzer bytes like [`decode_bytes`] and returns the input as JSON.
+    pub from_bytes: fn(&[u8]) -> Option<serde_json::Value>,
+}
+
+impl RegisteredTarget {
+    pub fn of<T: FuzzTarget>() -> RegisteredTarget
+    where
+        T::Input: for<'a> Arbitrary<'a>,
+    {
+        RegisteredTarget {
+            name: T::NAME,
+            method: T::METHOD,
+            path: T::PATH,
+            requires_auth: T::REQUIRES_AUTH,
+            fuzzcheck_test: T::FUZZCHECK_TEST,
+            run_json: run_json::<T>,
+            run_body: run_body::<T>,
+            probe_latency: probe_latency::<T>,
+            normalise: normalise::<T>,
+            from_bytes: from_bytes::<T>,
+        }
+    }
+}
+
+#[no_coverage]
+fn run_json<T: FuzzTarget>(data: &[u8]) -> Option<Verdict> {
+    serde_json::from_slice::<T::Input>(data)
+        .ok()
+        .map(|input| execute::<T>(&input))
+}
+
+#[no_coverage]
+fn run_body<T: FuzzTarget>(body: &serde_json::Value) -> Verdict {
+    execute_body::<T>(&body_path::<T>(body), body)
+}
+
+#[no_coverage]
+fn probe_latency<T: FuzzTarget>(body: &serde_json::Value) -> Vec<Injection> {
+    latency::probe::<T>(&body_path::<T>(body), body)
+}
+
+/// The path for a request body. Placeholders can only be filled if the body still fits the
+/// input type.
+#[no_coverage]
+fn body_path<T: FuzzTarget>(body: &serde_json::Value) -> String {
+    match serde_json::from_value::<T::Input>(body.clone()) {
+        Ok(input) => path::<T>(&input),
+        Err(_) => T::PATH.to_string(),
+    }
+}
+
+#[no_coverage]
+fn normalise<T: FuzzTarget>(value: serde_json::Value) -> Result<serde_json::Value, String> {
+    let input: T::Input = serde_json::from_value(value).map_err(|e| e.to_string())?;
+    serde_json::to_value(input).map_err(|e| e.to_string())
+}
+
+#[no_coverage]
+fn from_bytes<T: FuzzTarget>(data: &[u8]) -> Option<serde_json::Value>
+where
+    T::Input: for<'a> Arbitrary<'a>,
+{
+    decode_bytes::<T>(data).and_then(|input| serde_json::to_value(input).ok())
+}
+
+pub fn registry() -> Vec<RegisteredTarget> {
+    vec![
+        RegisteredTarget::of::<CreateRoom>(),
+        RegisteredTarget::of::<Login>(),
+        RegisteredTarget::of::<Scenario>(),
+    ]
+}
+
+pub fn find(name: &str) -> Option<RegisteredTarget> {
+    registry().into_iter().find(|t| t.name == name)
+}
+
+/// Generates a fuzzcheck test for a [`FuzzTarget`].
+#[macro_export]
+macro_rules! fuzzcheck_target {
+    ($test:ident, $target:ty) => {
+        #[test]
+        fn $test() {
+            use $crate::targets::FuzzTarget;
+            let config = match $crate::config() {
+                Ok(v) => v,
+                Err(e) => panic!("{}", e),
+            };
+            if !config.target_enabled(<$target>::NAME) {
+                return;
+            }
+            if let Err(e) = $crate::allowlist() {
+                panic!("{}", e);
+            }
+            if let Err(e) = <$target>::check_config() {
+                panic!("{}", e);
+            }
+            if !$crate::server_reachable() {
+                panic!("Failed to connect");
+            }
+            if <$target>::REQUIRES_AUTH {
+                if let Err(e) = $crate::access_token() {
+                    panic!("Unable to log in: {}", e);
+                }
+            }
+
+            // Inputs are kept for new coverage of the fuzzer or new behaviour of the server
+            let (coverage_sensor, coverage_pool) =
+                fuzzcheck::builder::default_sensor_and_pool().finish();
+            let sensor = fuzzcheck::sensors_and_pools::AndSensor(
+                coverage_sensor,
+                $crate::feedback::sensor::ServerSensor::default(),
+            );
+            let pool = fuzzcheck::sensors_and_pools::AndPool::new(
+                coverage_pool,
+                $crate::feedback::sensor::pool(),
+                128,
+            );
+
+            let campaign = &config.campaign;
+            let mut fuzzer = fuzzcheck::fuzz_test($crate::targets::run::<$target>)
+                .default_mutator()
+                .serde_serializer()
+                .sensor_and_pool(sensor, pool)
+                .arguments_from_cargo_fuzzcheck()
+                .stop_after_first_test_failure(!campaign.continue_on_failure);
+            if let Some(duration) = campaign.max_duration() {
+                fuzzer = fuzzer.stop_after_duration(duration);
+            }
+            if let Some(iterations) = campaign.max_iterations {
+                fuzzer = fuzzer.stop_after_iterations(iterations);
+            }
+            let result = fuzzer.launch();
+            if campaign.continue_on_failure {
+                let findings = config.output.findings.as_path();
+                if let Ok(buckets) = $crate::findings::buckets(findings, <$target>::NAME) {
+                    println!(
+                        "{}: {} distinct findings in {}",
+                        <$target>::NAME,
+                        buckets.len(),
+                        findings.join(<$target>::NAME).display()
+                    );
+                }
+            }
+            assert!(!result.found_test_failure);
+        }
+    };
+}
@@ -0,0 +1,51 @@
+use reqwest::Method;
+use serde_json::Value;
+
+use super::FuzzTarget;
+use crate::{authz, oracle::Verdict, postconditions, types::create_room::CreateRoomMagicJSON};
+
+pub struct CreateRoom;
+
+impl FuzzTarget for CreateRoom {
+    type Input = CreateRoomMagicJSON;
+
+    const NAME: &'static str = "createRoom";
+    const METHOD: Method = Method::POST;
+    const PATH: &'static str = "/_matrix/client/v3/createRoom";
+    const REQUIRES_AUTH: bool = true;
+    const FUZZCHECK_TEST: &'static str = "tests::fuzz_create_room";
+
+    fn sanitise(input: &CreateRoomMagicJSON) -> CreateRoomMagicJSON {
+        let mut json_data = input.clone();
+        for state in &mut json_data.initial_state {
+            if state.content.is_array()
+                || state.content.is_boolean()
+                || state.content.is_null()
+                || state.content.is_string()
+                || state.content.is_u64()
+            {
+                state.content = serde_json::Value::Object(serde_json::Map::new());
+            }
+        }
+
+        // HACK due to NUL in type or state_key. Not reported upstream yet, so it can not be a
+        // known bug
+        for state in json_data.initial_state.iter_mut() {
+            state._type = state._type.replace('\0', "");
+            state.state_key = state.state_key.replace('\0', "");
+        }
+
+        json_data
+    }
+
+    fn after_success(body: &Value, response: &Value) -> Option<Verdict> {
+        let room_id = response.get("room_id")?.as_str()?;
+        if let Some(verdict) = postconditions::check_created_room(body, room_id) {
+            return Some(verdict);
+        }
+        if !matches!(crate::config(), Ok(config) if config.identities.enabled) {
+            return None;
+        }
+        authz::check_room(room_id)
+    }
+}
@@ -0,0 +1,70 @@
+use reqwest::{Method, StatusCode};
+
+use super::FuzzTarget;
+use crate::{error::FuzzError, oracle::Verdict, session, types::LoginPostReq};
+
+pub struct Login;
+
+impl FuzzTarget for Login {
+    type Input = LoginPostReq;
+
+    const NAME: &'static str = "login";
+    const METHOD: Method = Method::POST;
+    const PATH: &'static str = "/_matrix/client/v3/login";
+    const REQUIRES_AUTH: bool = false;
+    const FUZZCHECK_TEST: &'static str = "tests::fuzz_login";
+
+    fn check_config() -> Result<(), FuzzError> {
+        crate::config()?.username().map(|_| ())
+    }
+
+    fn sanitise(input: &LoginPostReq) -> LoginPostReq {
+        let mut json_data = input.clone();
+        // We hardcode the type for better fuzzing
+        json_data._type = session::LOGIN_TYPE.to_string();
+
+        // Checked in `check_config`
+        let username = crate::config()
+            .and_then(|config| config.username())
+            .unwrap_or_default()
+            .to_string();
+
+        if json_data.user.is_some() {
+            json_data.user = Some(username.clone());
+        }
+        if let Some(identifier) = &mut json_data.identifier {
+            identifier.user = username;
+            identifier._type = "m.id.user".to_string();
+        }
+
+        if let Some(user) = &json_data.user {
+            if user.contains('\0') {
+                json_data.user = Some(user.replace('\0', ""));
+            }
+        }
+        if let Some(medium) = &json_data.medium {
+            if medium.contains('\0') {
+                json_data.medium = Some(medium.replace('\0', ""));
+            }
+        }
+        if let Some(address) = &json_data.address {
+            if address.contains('\0') {
+                json_data.address = Some(address.replace('\0', ""));
+            }
+        }
+
+        json_data
+    }
+
+    // Logging in with a fuzzed credential, a password under `password_auth` and a token otherwise,
+    // must never succeed.
+    fn classify(status: StatusCode, content: &str) -> Verdict {
+        if status.is_success() {
+            return Verdict::UnexpectedSuccess {
+                status: status.as_u16(),
+                content: content.to_string(),
+            };
+        }
+        super::classify_with_allowlist::<Self>(status, content)
+    }
+}
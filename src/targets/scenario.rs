@@ -0,0 +1,37 @@
+use reqwest::Method;
+use serde_json::Value;
+use std::time::Duration;
+
+use super::{CreateRoom, FuzzTarget};
+use crate::{oracle::Verdict, scenario, types::scenario::ScenarioJSON};
+
+/// Sequences of room actions by different users, starting with creating a room. See
+/// [`scenario::execute`].
+pub struct Scenario;
+
+impl FuzzTarget for Scenario {
+    type Input = ScenarioJSON;
+
+    const NAME: &'static str = "scenario";
+    const METHOD: Method = Method::POST;
+    const PATH: &'static str = CreateRoom::PATH;
+    const REQUIRES_AUTH: bool = true;
+    const FUZZCHECK_TEST: &'static str = "tests::fuzz_scenario";
+
+    fn sanitise(input: &ScenarioJSON) -> ScenarioJSON {
+        ScenarioJSON {
+            create_room: CreateRoom::sanitise(&input.create_room),
+            actions: input.actions.clone(),
+        }
+    }
+
+    fn exchange(_path: &str, body: &Value) -> (Verdict, Duration) {
+        match serde_json::from_value::<ScenarioJSON>(body.clone()) {
+            Ok(scenario) => scenario::execute(&scenario),
+            Err(e) => (
+                Verdict::FuzzerError(format!("not a scenario: {}", e)),
+                Duration::ZERO,
+            ),
+        }
+    }
+}
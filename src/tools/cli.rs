@@ -0,0 +1,392 @@
+use clap::{Parser, Subcommand};
+use matrix_fuzz::{
+    config::FuzzConfig,
+    corpus::{self, Format},
+    findings,
+    minimize::{minimize_with_budget, DEFAULT_MAX_CHECKS},
+    oracle::Verdict,
+    provision::{self, NewUser},
+    replay::{self, Outcome},
+    targets::{self, RegisteredTarget},
+};
+use serde_json::Value;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{self, Command as Process},
+};
+
+#[derive(Parser)]
+#[clap(name = "matrix-fuzz", about = "Fuzzer for Matrix homeservers")]
+struct Cli {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List the registered targets
+    List,
+    /// Run a fuzzcheck campaign against a target
+    Run {
+        target: String,
+        /// Extra arguments for `cargo fuzzcheck`
+        #[clap(last = true)]
+        args: Vec<String>,
+    },
+    /// Send saved inputs and report whether they still reproduce
+    Replay {
+        target: String,
+        /// An input file or a directory of inputs
+        path: PathBuf,
+        /// Send the files as request body as is instead of parsing them as target input
+        #[clap(long)]
+        raw: bool,
+        /// Record the current verdicts as expectation for later replays
+        #[clap(long)]
+        record: bool,
+    },
+    /// Shrink an input while it keeps producing the same finding
+    Minimize {
+        target: String,
+        file: PathBuf,
+        /// Where to write the minimised input. Prints it if unset.
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+        /// Maximum number of candidates to send to the server
+        #[clap(long, default_value_t = DEFAULT_MAX_CHECKS)]
+        max_checks: usize,
+    },
+    /// Append time-based injection payloads to every string of an input and report the ones
+    /// the server is slow to answer
+    Probe { target: String, file: PathBuf },
+    /// Replay every input below a directory, e.g. the findings of a target, and group them by
+    /// signature
+    Triage { target: String, dir: PathBuf },
+    /// List the saved finding buckets of a target
+    Findings {
+        target: String,
+        /// Findings directory. Defaults to the one from the config.
+        #[clap(long)]
+        dir: Option<PathBuf>,
+    },
+    /// Create a user through Synapse's shared secret registration
+    Provision {
+        /// Defaults to the configured user
+        username: Option<String>,
+        /// Defaults to the configured password
+        #[clap(long)]
+        password: Option<String>,
+        #[clap(long)]
+        admin: bool,
+        /// E.g. `support` or `bot`
+        #[clap(long)]
+        user_type: Option<String>,
+    },
+    /// Convert a corpus to the current input type of a target
+    Corpus {
+        #[clap(arg_enum)]
+        format: CorpusFormat,
+        target: String,
+        input: PathBuf,
+        output: PathBuf,
+        /// Decode inputs which are not JSON as raw fuzzer bytes, e.g. from an AFL queue
+        #[clap(long)]
+        decode_bytes: bool,
+    },
+}
+
+#[derive(Clone, Copy, clap::ArgEnum)]
+enum CorpusFormat {
+    ToFuzzcheck,
+    ToAfl,
+}
+
+fn main() {
+    let cli = Cli::parse();
+    match cli.command {
+        Command::List => list(),
+        Command::Run { target, args } => run(&find_target(&target), &args),
+        Command::Replay {
+            target,
+            path,
+            raw,
+            record,
+        } => replay_path(&find_target(&target), &path, raw, record),
+        Command::Minimize {
+            target,
+            file,
+            output,
+            max_checks,
+        } => minimize_file(&find_target(&target), &file, output.as_deref(), max_checks),
+        Command::Probe { target, file } => probe(&find_target(&target), &file),
+        Command::Triage { target, dir } => triage(&find_target(&target), &dir),
+        Command::Findings { target, dir } => list_findings(&find_target(&target), dir),
+        Command::Provision {
+            username,
+            password,
+            admin,
+            user_type,
+        } => provision_user(username, password, admin, user_type),
+        Command::Corpus {
+            format,
+            target,
+            input,
+            output,
+            decode_bytes,
+        } => convert_corpus(&find_target(&target), &input, &output, format, decode_bytes),
+    }
+}
+
+fn find_target(name: &str) -> RegisteredTarget {
+    match targets::find(name) {
+        Some(v) => v,
+        None => {
+            eprintln!("Unknown target {}. See `matrix-fuzz list`.", name);
+            process::exit(2);
+        }
+    }
+}
+
+fn config() -> &'static FuzzConfig {
+    match matrix_fuzz::config() {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    }
+}
+
+fn list() {
+    for target in targets::registry() {
+        println!(
+            "{:<12} {:<6} {:<40} auth: {:<5} fuzzcheck: {}",
+            target.name, target.method, target.path, target.requires_auth, target.fuzzcheck_test
+        );
+    }
+}
+
+fn run(target: &RegisteredTarget, args: &[String]) {
+    let status = Process::new("cargo")
+        .arg("fuzzcheck")
+        .arg(target.fuzzcheck_test)
+        .args(args)
+        .status();
+    match status {
+        Ok(status) => process::exit(status.code().unwrap_or(1)),
+        Err(e) => {
+            eprintln!("Failed to run cargo fuzzcheck ({})", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn read_input(file: &Path) -> Value {
+    let content = match fs::read(file) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Unable to read {} ({})", file.display(), e);
+            process::exit(2);
+        }
+    };
+    match serde_json::from_slice(&content) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{} is not JSON ({})", file.display(), e);
+            process::exit(2);
+        }
+    }
+}
+
+fn replay_value(target: &RegisteredTarget, value: &Value) -> Option<Verdict> {
+    replay::send_input(target, &serde_json::to_vec(value).unwrap(), false).ok()
+}
+
+fn replay_path(target: &RegisteredTarget, path: &Path, raw: bool, record: bool) {
+    let results = if path.is_dir() {
+        match replay::replay_dir(target, path, raw) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("Unable to read {} ({})", path.display(), e);
+                process::exit(2);
+            }
+        }
+    } else {
+        vec![replay::replay_file(target, path, raw)]
+    };
+
+    let mut still_failing = false;
+    for result in &results {
+        println!("{}: {}", result.path.display(), result.outcome);
+        if let Some(verdict) = &result.verdict {
+            println!("    {}", verdict);
+            if record && verdict.unlabelled().is_finding() {
+                if let Err(e) = replay::write_expectation(&result.path, verdict) {
+                    eprintln!("Unable to record expectation ({})", e);
+                }
+            }
+        }
+        still_failing |= matches!(
+            result.outcome,
+            Outcome::Reproduced | Outcome::Changed { .. }
+        );
+    }
+    if still_failing {
+        process::exit(1);
+    }
+}
+
+fn minimize_file(target: &RegisteredTarget, file: &Path, output: Option<&Path>, max_checks: usize) {
+    let input = read_input(file);
+    let original = match replay_value(target, &input) {
+        Some(v) => v,
+        None => {
+            eprintln!("{} is not a valid {} input", file.display(), target.name);
+            process::exit(2);
+        }
+    };
+    if !original.unlabelled().is_finding() {
+        eprintln!("{} does not cause a finding ({})", file.display(), original);
+        process::exit(1);
+    }
+    println!("Minimising {}", original);
+
+    let before = input.to_string().len();
+    let minimised = minimize_with_budget(
+        input,
+        max_checks,
+        |candidate| matches!(replay_value(target, candidate), Some(v) if original.same_finding(&v)),
+    );
+    eprintln!(
+        "Shrunk from {} to {} bytes",
+        before,
+        minimised.to_string().len()
+    );
+    let json = serde_json::to_string_pretty(&minimised).unwrap();
+    match output {
+        Some(path) => {
+            if let Err(e) = fs::write(path, json) {
+                eprintln!("Unable to write {} ({})", path.display(), e);
+                process::exit(1);
+            }
+        }
+        None => println!("{}", json),
+    }
+}
+
+fn probe(target: &RegisteredTarget, file: &Path) {
+    let dir = &config().output.findings;
+    let input = read_input(file);
+    let injections = (target.probe_latency)(&input);
+    for injection in &injections {
+        println!(
+            "{} at {}: {}",
+            injection.payload, injection.pointer, injection.verdict
+        );
+        match findings::record(dir, &injection.request, &injection.verdict) {
+            Ok(recorded) => println!("    {}", recorded.path.display()),
+            Err(e) => eprintln!("    Unable to save finding ({})", e),
+        }
+    }
+    println!("{} injections caused a finding", injections.len());
+    if !injections.is_empty() {
+        process::exit(1);
+    }
+}
+
+fn triage(target: &RegisteredTarget, dir: &Path) {
+    let groups = match replay::triage(target, dir) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Unable to read {} ({})", dir.display(), e);
+            process::exit(2);
+        }
+    };
+    for (group, paths) in groups {
+        println!("{} ({} inputs)", group, paths.len());
+        for path in paths {
+            println!("    {}", path.display());
+        }
+    }
+}
+
+fn list_findings(target: &RegisteredTarget, dir: Option<PathBuf>) {
+    let dir = dir.unwrap_or_else(|| config().output.findings.clone());
+    let buckets = match findings::buckets(&dir, target.name) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Unable to read {} ({})", dir.display(), e);
+            process::exit(2);
+        }
+    };
+    println!("{} distinct findings", buckets.len());
+    for (path, bucket) in buckets {
+        println!(
+            "{} x{} {}",
+            bucket.signature.id(),
+            bucket.count,
+            bucket.signature
+        );
+        println!("    {}", path.display());
+    }
+}
+
+fn provision_user(
+    username: Option<String>,
+    password: Option<String>,
+    admin: bool,
+    user_type: Option<String>,
+) {
+    let config = config();
+    let configured = |setting: Result<&str, _>| match setting {
+        Ok(v) => v.to_string(),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+    let username = username.unwrap_or_else(|| configured(config.username()));
+    let password = password.unwrap_or_else(|| configured(config.password()));
+    let user = NewUser {
+        username: &username,
+        password: &password,
+        admin,
+        user_type: user_type.as_deref(),
+    };
+    match provision::ensure_user(&user) {
+        Ok(true) => println!("Created {}", username),
+        Ok(false) => println!("{} already exists", username),
+        Err(e) => {
+            eprintln!("Unable to create {} ({})", username, e);
+            process::exit(1);
+        }
+    }
+}
+
+fn convert_corpus(
+    target: &RegisteredTarget,
+    input: &Path,
+    output: &Path,
+    format: CorpusFormat,
+    decode_bytes: bool,
+) {
+    let format = match format {
+        CorpusFormat::ToFuzzcheck => Format::Fuzzcheck,
+        CorpusFormat::ToAfl => Format::Afl,
+    };
+    let report = match corpus::convert(target, input, output, format, decode_bytes) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Failed to convert corpus: {}", e);
+            process::exit(1);
+        }
+    };
+    println!(
+        "Converted {} inputs ({} migrated from an older schema, {} decoded from raw bytes)",
+        report.converted, report.migrated, report.decoded
+    );
+    for (path, reason) in &report.skipped {
+        println!("Skipped {}: {}", path.display(), reason);
+    }
+}
@@ -1,4 +1,6 @@
 pub mod create_room;
+pub mod json;
+pub mod scenario;
 
 use arbitrary::Arbitrary;
 use fuzzcheck::DefaultMutator;
@@ -20,6 +22,29 @@ pub struct LoginPost {
     pub user_id: String,
     pub access_token: String,
     pub home_server: String,
+    pub refresh_token: Option<String>,
+    pub expires_in_ms: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshPost {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub expires_in_ms: Option<u64>,
+}
+
+/// An event of the response to `GET /rooms/{roomId}/state`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StateEvent {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub state_key: String,
+    pub content: serde_json::Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RoomVisibility {
+    pub visibility: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, DefaultMutator, Arbitrary)]
@@ -1,9 +1,11 @@
-use arbitrary::Arbitrary;
+use arbitrary::{Arbitrary, Unstructured};
 use fuzzcheck::{DefaultMutator, Mutator};
 use fuzzcheck_serde_json_generator::json_value_mutator;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use super::json::{arbitrary_object, arbitrary_value};
+
 type ValueMutator = impl Mutator<Value>;
 
 fn creation_content_skip(value: &Value) -> bool {
@@ -29,26 +31,25 @@ fn room_version_skip(value: &Option<String>) -> bool {
 
 #[derive(Clone, Serialize, Deserialize, Debug, Default, DefaultMutator)]
 pub struct CreateRoomMagicJSON {
-    #[serde(skip_serializing_if = "creation_content_skip")]
+    #[serde(default, skip_serializing_if = "creation_content_skip")]
     #[field_mutator(ValueMutator = { json_value_mutator() })]
     pub creation_content: Value,
     //#[serde(skip_serializing_if = "Option::is_none")]
     // Required for more fuzzing results
+    #[serde(default)]
     pub initial_state: Vec<StateEventJSON>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub invite: Option<Vec<String>>,
-    // Due to https://github.com/matrix-org/synapse/issues/13512
-    //#[serde(skip_serializing_if = "Option::is_none")]
-    //pub invite_3pid: Option<Vec<Invite3pid>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invite_3pid: Option<Vec<Invite3pid>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub is_direct: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub preset: Option<String>,
-    // Disabled to have more fuzz results
-    //#[serde(skip_serializing_if = "Option::is_none")]
-    //pub room_alias_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub room_alias_name: Option<String>,
     // Disabled to have more fuzz results
     //#[serde(skip_serializing_if = "room_version_skip")]
     //pub room_version: Option<String>,
@@ -68,9 +69,37 @@ pub struct Invite3pid {
 
 #[derive(Clone, Serialize, Deserialize, Debug, Default, DefaultMutator)]
 pub struct StateEventJSON {
+    #[serde(default)]
     #[field_mutator(ValueMutator = {json_value_mutator()})]
     pub content: Value,
     #[serde(rename = "type")]
     pub _type: String,
     pub state_key: String,
 }
+
+impl<'a> Arbitrary<'a> for CreateRoomMagicJSON {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(CreateRoomMagicJSON {
+            creation_content: arbitrary_object(u)?,
+            initial_state: u.arbitrary()?,
+            invite: u.arbitrary()?,
+            invite_3pid: u.arbitrary()?,
+            is_direct: u.arbitrary()?,
+            name: u.arbitrary()?,
+            preset: u.arbitrary()?,
+            room_alias_name: u.arbitrary()?,
+            topic: u.arbitrary()?,
+            visibility: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for StateEventJSON {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(StateEventJSON {
+            content: arbitrary_value(u)?,
+            _type: u.arbitrary()?,
+            state_key: u.arbitrary()?,
+        })
+    }
+}
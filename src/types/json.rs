@@ -0,0 +1,48 @@
+use arbitrary::{Result, Unstructured};
+use serde_json::{Map, Number, Value};
+
+/// Nesting limit for generated arrays and objects.
+const MAX_DEPTH: usize = 4;
+/// Maximum number of elements of a generated array or object.
+const MAX_LEN: usize = 8;
+
+/// Generates an arbitrary JSON value, as `serde_json::Value` has no `Arbitrary` impl.
+pub fn arbitrary_value(u: &mut Unstructured) -> Result<Value> {
+    value_with_depth(u, 0)
+}
+
+/// Generates an arbitrary JSON object. Most fields of the Matrix API only accept objects, so
+/// this reaches the server side validation more often than [`arbitrary_value`].
+pub fn arbitrary_object(u: &mut Unstructured) -> Result<Value> {
+    object_with_depth(u, 0)
+}
+
+fn value_with_depth(u: &mut Unstructured, depth: usize) -> Result<Value> {
+    let max_kind = if depth >= MAX_DEPTH { 4 } else { 6 };
+    Ok(match u.int_in_range(0..=max_kind)? {
+        0 => Value::Null,
+        1 => Value::Bool(u.arbitrary()?),
+        2 => Value::from(u.arbitrary::<i64>()?),
+        3 => Number::from_f64(u.arbitrary()?).map_or(Value::Null, Value::Number),
+        4 => Value::String(u.arbitrary()?),
+        5 => {
+            let len = u.int_in_range(0..=MAX_LEN)?;
+            let mut array = Vec::with_capacity(len);
+            for _ in 0..len {
+                array.push(value_with_depth(u, depth + 1)?);
+            }
+            Value::Array(array)
+        }
+        _ => object_with_depth(u, depth)?,
+    })
+}
+
+fn object_with_depth(u: &mut Unstructured, depth: usize) -> Result<Value> {
+    let len = u.int_in_range(0..=MAX_LEN)?;
+    let mut object = Map::new();
+    for _ in 0..len {
+        let key: String = u.arbitrary()?;
+        object.insert(key, value_with_depth(u, depth + 1)?);
+    }
+    Ok(Value::Object(object))
+}
@@ -0,0 +1,84 @@
+use arbitrary::Arbitrary;
+use fuzzcheck::DefaultMutator;
+use serde::{Deserialize, Serialize};
+
+use super::create_room::{CreateRoomMagicJSON, StateEventJSON};
+use crate::identities::Role;
+
+/// A sequence of requests, starting with creating a room.
+///
+/// Actions refer to earlier results by index: `room` picks one of the rooms created so far
+/// (the first step's room is 0, upgrades add the replacement room) and `event` one of the
+/// events sent so far. Indexes wrap around, so every generated index refers to something once
+/// there is at least one room or event.
+#[derive(Clone, Serialize, Deserialize, Debug, Default, DefaultMutator, Arbitrary)]
+pub struct ScenarioJSON {
+    pub create_room: CreateRoomMagicJSON,
+    #[serde(default)]
+    pub actions: Vec<Action>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, DefaultMutator, Arbitrary)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    Invite {
+        actor: Role,
+        room: usize,
+        invitee: Role,
+    },
+    Join {
+        actor: Role,
+        room: usize,
+    },
+    Leave {
+        actor: Role,
+        room: usize,
+    },
+    SendState {
+        actor: Role,
+        room: usize,
+        event: StateEventJSON,
+    },
+    SendMessage {
+        actor: Role,
+        room: usize,
+        body: String,
+    },
+    Redact {
+        actor: Role,
+        room: usize,
+        event: usize,
+        reason: Option<String>,
+    },
+    Upgrade {
+        actor: Role,
+        room: usize,
+        new_version: String,
+    },
+}
+
+impl Action {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Invite { .. } => "invite",
+            Action::Join { .. } => "join",
+            Action::Leave { .. } => "leave",
+            Action::SendState { .. } => "send_state",
+            Action::SendMessage { .. } => "send_message",
+            Action::Redact { .. } => "redact",
+            Action::Upgrade { .. } => "upgrade",
+        }
+    }
+
+    pub fn actor(&self) -> Role {
+        match self {
+            Action::Invite { actor, .. }
+            | Action::Join { actor, .. }
+            | Action::Leave { actor, .. }
+            | Action::SendState { actor, .. }
+            | Action::SendMessage { actor, .. }
+            | Action::Redact { actor, .. }
+            | Action::Upgrade { actor, .. } => *actor,
+        }
+    }
+}